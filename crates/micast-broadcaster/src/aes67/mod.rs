@@ -0,0 +1,117 @@
+/// AES67 interoperability profile
+/// 
+/// L24 at 48 kHz with 1 ms packet time, send to a multicast group and synchronized
+/// by a PTP clock (RFC 7273) instead of the `NetTimeProvider`
+use crate::sdp::SessionDescription;
+use crate::clock::ClockSource;
+
+pub(crate) const AES67_CLOCK_RATE: i32 = 48000;
+/// packet time of AES67 streams in ms
+pub(crate) const AES67_PTIME_MS: u64 = 1;
+
+/// Options of the AES67 profile, used by the `RTPServer` and the `PlaybackClient`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aes67Options {
+    /// multicast group where the stream is send to / received from
+    pub multicast_group: String,
+    /// ttl of the multicast packets
+    pub ttl: u32,
+    /// PTP domain of the grandmaster clock
+    pub ptp_domain: u32,
+    /// number of audio channels
+    pub channels: i32,
+    /// payload type of the stream
+    pub payload_type: u32,
+    /// reference clock of the timestamps (`a=ts-refclk`), e.g. `ptp=IEEE1588-2008:39-A7-94-FF-FE-07-CB-D0:0`.
+    /// the client syncs to it (RFC 7273), without it the stream is played unsynchronized
+    pub ts_refclk: Option<String>,
+    /// media clock of the stream (`a=mediaclk`), e.g. `direct=0`
+    pub mediaclk: Option<String>,
+}
+
+impl Default for Aes67Options {
+    fn default() -> Self {
+        Aes67Options {
+            multicast_group: "239.69.0.1".to_string(),
+            ttl: 16,
+            ptp_domain: 0,
+            channels: 2,
+            payload_type: 96,
+            ts_refclk: None,
+            mediaclk: None,
+        }
+    }
+}
+
+impl Aes67Options {
+    /// RTP caps of the AES67 stream
+    pub(crate) fn caps(&self) -> gst::Caps {
+        let mut caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "audio")
+            .field("payload", self.payload_type as i32)
+            .field("clock-rate", AES67_CLOCK_RATE)
+            .field("encoding-name", "L24")
+            .field("channels", self.channels);
+
+        // read by the jitterbuffer for the `rfc7273-sync` of the rtpbin
+        if let Some(ts_refclk) = &self.ts_refclk {
+            caps = caps.field("a-ts-refclk", ts_refclk);
+        }
+        if let Some(mediaclk) = &self.mediaclk {
+            caps = caps.field("a-mediaclk", mediaclk);
+        }
+
+        caps.build()
+    }
+
+    /// options to receive an announced AES67 stream, `None` if the stream
+    /// is not L24 synced by a PTP clock
+    pub fn from_session_description(description: &SessionDescription) -> Option<Aes67Options> {
+        // ptp=IEEE1588-2008:XX-XX-XX-XX-XX-XX-XX-XX:domain or ptp=IEEE1588-2008:traceable
+        let ts_refclk = description.attribute("ts-refclk")?;
+        let ptp_domain = ts_refclk.strip_prefix("ptp=")?.split(':').nth(2)
+            .and_then(|domain| domain.parse().ok())
+            .unwrap_or(0);
+
+        let format = description.formats.first()?;
+        if format.encoding_name != "L24" || format.clock_rate != AES67_CLOCK_RATE as u32 {
+            return None;
+        }
+
+        Some(Aes67Options {
+            multicast_group: description.connection_address.clone(),
+            ttl: description.ttl.unwrap_or(16),
+            ptp_domain,
+            channels: format.channels.unwrap_or(2) as i32,
+            payload_type: format.payload_type,
+            ts_refclk: Some(ts_refclk.to_string()),
+            mediaclk: description.attribute("mediaclk").map(|mediaclk| mediaclk.to_string()),
+        })
+    }
+
+    /// the PTP clock of the stream
+    pub fn clock_source(&self) -> ClockSource {
+        ClockSource::Ptp { domain: self.ptp_domain }
+    }
+
+    /// RFC 7273 clock signalling attributes for the SDP
+    pub(crate) fn sdp_attributes(&self, grandmaster_clock_id: Option<u64>) -> Vec<String> {
+        let refclk = match grandmaster_clock_id {
+            Some(id) if id != 0 => {
+                let id = id.to_be_bytes()
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect::<Vec<_>>()
+                    .join("-");
+                format!("ts-refclk:ptp=IEEE1588-2008:{}:{}", id, self.ptp_domain)
+            },
+            _ => "ts-refclk:ptp=IEEE1588-2008:traceable".to_string(),
+        };
+
+        vec![
+            refclk,
+            "mediaclk:direct=0".to_string(),
+            format!("ptime:{}", AES67_PTIME_MS),
+        ]
+    }
+}
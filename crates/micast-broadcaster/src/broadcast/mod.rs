@@ -7,7 +7,13 @@ use gst::glib;
 use crate::helpers::*;
 use crate::sleep_ms;
 use crate::services::dedector_server;
+use crate::services::discovery::Announcement;
+use crate::services::InterfaceFilter;
+use crate::services::mdns::MdnsAdvertiser;
+use crate::services::control::ControlCommand;
 use crate::rtpserver;
+use crate::rtspserver;
+use crate::clock::{self, ClockSource, SyncStatus};
 
 use std::{
     sync::{Arc, Weak},
@@ -17,8 +23,6 @@ use parking_lot::Mutex;
 
 use log::{debug, warn, trace};
 
-//pub(crate) const ENCRYPTION_ENABLED:bool = true;
-
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum OutputMode {
@@ -32,6 +36,42 @@ impl Default for OutputMode {
     }
 }
 
+/// Options for the Broadcast Server
+#[derive(Debug, Clone, Default)]
+pub struct BroadcastOptions {
+    /// options for the rtpserver used in network mode
+    pub rtpserver: rtpserver::RTPServerOptions,
+    /// also serve the program on demand via RTSP, works alongside the rtpserver.
+    /// not possible with the encryption of the rtpserver, the RTSP stream is not encrypted
+    pub rtspserver: Option<rtspserver::RTSPServerOptions>,
+    /// clock to sync the clients, the clients have to use the same clock source.
+    /// in AES67 mode the PTP clock of the AES67 options is always used
+    pub clock: ClockSource,
+    /// unique id of the server in the discovery announcement, per default the hostname
+    pub server_id: Option<String>,
+    /// human readable name of the server in the discovery announcement
+    pub server_name: Option<String>,
+    /// names of the channels announced to the clients
+    pub channels: Vec<String>,
+    /// zone announced to the clients, e.g. a room or a floor
+    pub zone: Option<String>,
+    /// priority announced to the clients, the clients prefer the server with the highest priority
+    pub priority: u32,
+    /// interfaces where the server is announced
+    pub interfaces: InterfaceFilter,
+    /// also advertise the server as `_micast._udp` DNS-SD service via mDNS
+    pub mdns: bool,
+}
+
+/// hostname of the machine, used as the server id if none is set
+fn default_server_id() -> String {
+    std::fs::read_to_string("/etc/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .ok()
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| format!("micast-{}", std::process::id()))
+}
+
 // Strong reference to our broadcast server state
 #[derive(Debug, Clone)]
 pub struct Broadcast(Arc<BroadcastInner>);
@@ -46,9 +86,16 @@ pub struct BroadcastInner {
     pub pipeline: gst::Pipeline,
     pub appsrc: gst_app::AppSrc,
     #[allow(dead_code)]
-    net_clock: gst_net::NetTimeProvider,
+    net_clock: Option<gst_net::NetTimeProvider>,
+    clock: gst::Clock,
+    clock_source: ClockSource,
+    #[allow(dead_code)]
+    mdns: Option<MdnsAdvertiser>,
 
     rtpserver: Mutex<Option<rtpserver::RTPServer>>,
+    rtspserver: Mutex<Option<rtspserver::RTSPServer>>,
+    // the rtpserver encrypts the program
+    encrypted: bool,
     local_bin: Mutex<Option<gst::Element>>,
     tee_bin: gst::Element,
 
@@ -92,19 +139,66 @@ impl Broadcast {
     ) -> Result<
         Self,
         anyhow::Error,
+    > {
+        Self::new_with_options(start_port, current_output, BroadcastOptions::default())
+    }
+
+    /// Announcement of this server for the discovery of the clients
+    fn announcement(rtp_port: u32, clock_source: &ClockSource, options: &BroadcastOptions) -> Announcement {
+        let server_id = options.server_id.clone().unwrap_or_else(default_server_id);
+        let name = options.server_name.clone().unwrap_or_else(|| server_id.clone());
+
+        let mut announcement = Announcement::new(&server_id, &name, rtp_port);
+        announcement.clock_port = match clock_source {
+            ClockSource::NetTime { port } => Some(*port as u32),
+            _ => None,
+        };
+        announcement.codec = if options.rtpserver.as_opus && options.rtpserver.aes67.is_none() {
+            "OPUS".to_string()
+        } else {
+            "L24".to_string()
+        };
+        announcement.multicast_group = options.rtpserver.aes67.as_ref().map(|aes67| aes67.multicast_group.clone());
+        announcement.channels = options.channels.clone();
+        announcement.zone = options.zone.clone();
+        announcement.priority = options.priority;
+        announcement.encrypted = options.rtpserver.encryption;
+        announcement.cipher_suite = options.rtpserver.cipher_suite.to_string();
+        announcement
+    }
+
+    /// Creates the **Broadcast Server** with additional options
+    /// 
+    /// see [`Broadcast::new`] for the arguments
+    /// * `options` - additional options, see [`BroadcastOptions`]
+    pub fn new_with_options(
+        start_port: u32,
+        current_output: OutputMode,
+        options: BroadcastOptions,
+    ) -> Result<
+        Self,
+        anyhow::Error,
     > {
         let _ = gst::init();
 
-        // setup and init NetTime Provider (aka NTPServer)
-        let clock = gst::SystemClock::obtain();
-        let net_clock = gst_net::NetTimeProvider::new(&clock, None, 8555)?;
-        clock.set_property("clock-type", &gst::ClockType::Realtime);
+        // aes67 devices are synced by the ptp grandmaster
+        let clock_source = match &options.rtpserver.aes67 {
+            Some(aes67) => aes67.clock_source(),
+            None => options.clock.clone(),
+        };
+        let (clock, net_clock) = clock::create_server_clock(&clock_source, options.rtpserver.dscp.control)?;
 
         let pipeline = gst::Pipeline::new(None);
         pipeline.use_clock(Some(&clock));
         
         // add ip broadcaster (currently wrong name, not only for clock although for server address)
-        dedector_server::service(start_port)?;
+        let announcement = Self::announcement(start_port, &clock_source, &options);
+        let mdns = if options.mdns {
+            Some(MdnsAdvertiser::new(&announcement)?)
+        } else {
+            None
+        };
+        dedector_server::service(announcement, options.interfaces.clone(), options.rtpserver.dscp.control)?;
 
         // caps for AppSrc element from rodio
         let maincaps = gst::Caps::builder("audio/x-raw")
@@ -137,7 +231,10 @@ impl Broadcast {
         pipeline.add(&tee_bin)?;
         mainresampler.link(&tee_bin)?;
 
-        let local_rtpserver = rtpserver::RTPServer::new(true, true)?;
+        let local_rtpserver = rtpserver::RTPServer::with_options(rtpserver::RTPServerOptions {
+            rtp_port: start_port,
+            ..options.rtpserver.clone()
+        })?;
 
         let mut rtpserver: Option<rtpserver::RTPServer> = Some(local_rtpserver.clone());
         // set listening addresses... 
@@ -182,9 +279,14 @@ impl Broadcast {
             appsrc,
             current_output: Mutex::new(current_output),
             rtpserver: Mutex::new(rtpserver),
+            rtspserver: Mutex::new(None),
+            encrypted: options.rtpserver.encryption,
             local_bin: Mutex::new(local_bin),
             tee_bin,
             net_clock,
+            clock,
+            clock_source,
+            mdns,
         }));
         
         let broadcast_weak = broadcast.downgrade();
@@ -227,6 +329,10 @@ impl Broadcast {
             None
         });
 
+        if let Some(rtspserver_options) = options.rtspserver {
+            broadcast.enable_rtspserver(rtspserver_options)?;
+        }
+
         let weak_pipeline = broadcast.pipeline.downgrade();
         glib::timeout_add(std::time::Duration::from_secs(5), move || {
             let pipeline = match weak_pipeline.upgrade() {
@@ -400,6 +506,132 @@ impl Broadcast {
         Ok(())
     }
 
+    /// # sync_status
+    /// 
+    /// synchronization status of the clock which is provided to the clients
+    /// 
+    pub fn sync_status(&self) -> SyncStatus {
+        SyncStatus::of_clock(&self.clock_source, &self.clock)
+    }
+
+    /// # sdp
+    /// 
+    /// SDP description of the network stream, see [`rtpserver::RTPServer::sdp`]
+    /// 
+    pub fn sdp(&self) -> Result<String, anyhow::Error> {
+        match self.rtpserver.lock().as_ref() {
+            Some(rtpserver) => rtpserver.sdp(),
+            None => Err(anyhow::anyhow!("no rtpserver running")),
+        }
+    }
+
+    /// # client_status
+    /// 
+    /// status and last report of the connected clients, see [`rtpserver::RTPServer::client_status`]
+    /// 
+    pub fn client_status(&self) -> Vec<rtpserver::ClientStatus> {
+        match self.rtpserver.lock().as_ref() {
+            Some(rtpserver) => rtpserver.client_status(),
+            None => Vec::new(),
+        }
+    }
+
+    /// # send_command
+    /// 
+    /// send a command of the remote control channel to all connected clients,
+    /// see [`rtpserver::RTPServer::send_command`]
+    /// 
+    pub fn send_command(&self, command: &ControlCommand, timeout: std::time::Duration) -> Vec<(std::net::IpAddr, Result<(), anyhow::Error>)> {
+        // the clients may take a while to acknowledge, do not block the rtpserver meanwhile
+        let rtpserver = self.rtpserver.lock().clone();
+        match rtpserver {
+            Some(rtpserver) => rtpserver.send_command(command, timeout),
+            None => Vec::new(),
+        }
+    }
+
+    /// # rotate_key
+    /// 
+    /// switch to a new SRTP key now, see [`rtpserver::RTPServer::rotate_key`]
+    /// 
+    pub fn rotate_key(&self) -> Result<Vec<(std::net::IpAddr, Result<(), anyhow::Error>)>, anyhow::Error> {
+        let rtpserver = self.rtpserver.lock().clone();
+        match rtpserver {
+            Some(rtpserver) => rtpserver.rotate_key(),
+            None => Err(anyhow::anyhow!("no rtpserver running")),
+        }
+    }
+
+    /// # events
+    /// 
+    /// events of the rtpserver, e.g. rejected clients, see [`rtpserver::RTPServer::events`]
+    /// 
+    pub fn events(&self) -> Option<crossbeam_channel::Receiver<rtpserver::ServerEvent>> {
+        self.rtpserver.lock().as_ref().map(|rtpserver| rtpserver.events())
+    }
+
+    /// # enable_rtspserver
+    /// 
+    /// serves the program additionally via RTSP, independent of the current output.
+    /// fails if the rtpserver encrypts the program, the RTSP stream would not be encrypted
+    /// 
+    pub fn enable_rtspserver(&self, options: rtspserver::RTSPServerOptions) -> Result<(), anyhow::Error> {
+        if self.encrypted {
+            return Err(anyhow::anyhow!("the rtspserver would serve the encrypted program unencrypted"));
+        }
+
+        let mut locked_rtspserver = self.rtspserver.lock();
+        if locked_rtspserver.is_some() {
+            debug!("rtspserver already enabled");
+            return Ok(());
+        }
+
+        let server = rtspserver::RTSPServer::new(options)?;
+        let element = server.get_element();
+
+        self.pipeline.add(&element)?;
+        self.tee_bin.link(&element)?;
+        element.sync_state_with_parent()?;
+
+        *locked_rtspserver = Some(server);
+
+        Ok(())
+    }
+
+    /// # disable_rtspserver
+    /// 
+    /// stops serving the program via RTSP
+    /// 
+    pub fn disable_rtspserver(&self) {
+        let server = match self.rtspserver.lock().take() {
+            Some(server) => server,
+            None => return,
+        };
+
+        let element = server.get_element();
+        let ghostpad = element.static_pad("sink").unwrap();
+        let teepad = match ghostpad.peer() {
+            Some(teepad) => teepad,
+            None => return,
+        };
+
+        let weak_self = self.downgrade();
+        let inner_teepad = teepad.clone();
+        let server = Mutex::new(Some(server));
+        trace!("add probe to remove rtsp connection");
+        teepad.add_probe(gst::PadProbeType::BLOCK, move |pad, info| {
+            pad.remove_probe(info.id.take().unwrap());
+            let this = upgrade_weak!(weak_self, gst::PadProbeReturn::Remove);
+            let _ = element.set_state(gst::State::Null);
+            let _ = this.pipeline.remove(&element);
+            let _ = this.tee_bin.release_request_pad(&inner_teepad);
+            // stops the rtsp server
+            drop(server.lock().take());
+
+            gst::PadProbeReturn::Remove
+        });
+    }
+
     /// # stop
     ///
     /// Stops the Gstreamer Pipeline by set state to Null
@@ -0,0 +1,119 @@
+/// Clock sources for the synchronization between broadcaster and clients
+/// 
+/// the broadcaster and all clients have to use the same clock source
+use gst::prelude::*;
+use log::{debug, warn};
+
+use crate::qos;
+
+/// default port of the `NetTimeProvider`
+pub const DEFAULT_NET_TIME_PORT: i32 = 8555;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClockSource {
+    /// the broadcaster provides its system clock with a `NetTimeProvider`, 
+    /// the clients sync with a `NetClientClock` to the broadcaster
+    NetTime { port: i32 },
+    /// broadcaster and clients sync to the PTP grandmaster of the domain,
+    /// much tighter sync on networks with PTP-aware switches
+    Ptp { domain: u32 },
+    /// broadcaster and clients sync to the NTP server 
+    Ntp { address: String, port: i32 },
+}
+
+impl Default for ClockSource {
+    fn default() -> Self {
+        ClockSource::NetTime { port: DEFAULT_NET_TIME_PORT }
+    }
+}
+
+/// Synchronization status of a clock
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncStatus {
+    /// source of the clock
+    pub source: ClockSource,
+    /// the clock is synced to its source
+    pub synced: bool,
+    /// current time of the clock
+    pub time: Option<gst::ClockTime>,
+    /// id of the PTP grandmaster, only for PTP clocks
+    pub grandmaster_clock_id: Option<u64>,
+}
+
+impl SyncStatus {
+    pub(crate) fn of_clock(source: &ClockSource, clock: &gst::Clock) -> Self {
+        SyncStatus {
+            source: source.clone(),
+            synced: clock.is_synced(),
+            time: clock.time(),
+            grandmaster_clock_id: grandmaster_clock_id(clock),
+        }
+    }
+}
+
+/// creates the clock of the broadcaster, for `NetTime` also the `NetTimeProvider`.
+/// the clock packets are marked with `dscp`
+pub(crate) fn create_server_clock(source: &ClockSource, dscp: Option<u8>) -> Result<(gst::Clock, Option<gst_net::NetTimeProvider>), anyhow::Error> {
+    match source {
+        ClockSource::NetTime { port } => {
+            // setup and init NetTime Provider (aka NTPServer)
+            let clock = gst::SystemClock::obtain();
+            let net_clock = gst_net::NetTimeProvider::new(&clock, None, *port)?;
+            qos::mark_object(&net_clock, dscp);
+            clock.set_property("clock-type", &gst::ClockType::Realtime);
+            Ok((clock, Some(net_clock)))
+        },
+        ClockSource::Ptp { domain } => Ok((create_ptp_clock(*domain)?, None)),
+        ClockSource::Ntp { address, port } => Ok((create_ntp_clock(address, *port, dscp), None)),
+    }
+}
+
+/// creates the clock of a client, `server_address` is used for `NetTime`.
+/// the clock packets are marked with `dscp`
+pub(crate) fn create_client_clock(source: &ClockSource, server_address: &str, dscp: Option<u8>) -> Result<gst::Clock, anyhow::Error> {
+    match source {
+        ClockSource::NetTime { port } => {
+            let clock = gst_net::NetClientClock::new(None, server_address, *port, gst::ClockTime::ZERO);
+            qos::mark_object(&clock, dscp);
+            Ok(clock.upcast())
+        },
+        ClockSource::Ptp { domain } => create_ptp_clock(*domain),
+        ClockSource::Ntp { address, port } => Ok(create_ntp_clock(address, *port, dscp)),
+    }
+}
+
+fn create_ntp_clock(address: &str, port: i32, dscp: Option<u8>) -> gst::Clock {
+    debug!("use ntp clock of {}:{}", address, port);
+    let clock = gst_net::NtpClock::new(None, address, port, gst::ClockTime::ZERO);
+    qos::mark_object(&clock, dscp);
+    clock.upcast()
+}
+
+/// creates a PTP clock for the given domain, initialize PTP if required
+pub(crate) fn create_ptp_clock(domain: u32) -> Result<gst::Clock, anyhow::Error> {
+    if !gst_net::PtpClock::is_initialized() {
+        debug!("initialize ptp");
+        gst_net::PtpClock::init(None, &[])?;
+    }
+
+    debug!("use ptp clock on domain {}", domain);
+    let clock = gst_net::PtpClock::new(None, domain)?;
+    Ok(clock.upcast())
+}
+
+/// the net client and NTP clocks post their statistics (`gst-netclock-statistics`) on the bus
+pub(crate) fn post_statistics(clock: &gst::Clock, bus: &gst::Bus) {
+    if clock.has_property("bus", Some(gst::Bus::static_type())) {
+        clock.set_property("bus", bus);
+    }
+}
+
+/// grandmaster clock id of a PTP clock, `None` if the clock is not a PTP clock or not synced
+pub(crate) fn grandmaster_clock_id(clock: &gst::Clock) -> Option<u64> {
+    let clock = clock.downcast_ref::<gst_net::PtpClock>()?;
+    if !clock.is_synced() {
+        warn!("ptp clock is not synced, no grandmaster available");
+        return None;
+    }
+    Some(clock.property::<u64>("grandmaster-clock-id"))
+}
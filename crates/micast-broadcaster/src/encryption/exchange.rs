@@ -0,0 +1,94 @@
+/// X25519 key agreement between the server and each client
+///
+/// the client sends its public key with the signed confirmations, the server sends its public key
+/// with the signed keys it distributes. both derive the same wrapping key for the SRTP keys of the client,
+/// the signatures of the shared secret (`services::auth`) authenticate the public keys
+use std::fmt;
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const EXCHANGE_PURPOSE: &str = "micast-srtp-session-key";
+
+/// length of a public key
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+
+/// The key pair of the server or a client, new for every run
+pub struct KeyExchange {
+    secret: StaticSecret,
+    public: PublicKey,
+}
+
+// never print the secret
+impl fmt::Debug for KeyExchange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "KeyExchange {{ public: {} }}", hex::encode(self.public.as_bytes()))
+    }
+}
+
+impl KeyExchange {
+    pub fn new() -> Result<KeyExchange> {
+        let mut secret = [0u8; 32];
+        getrandom::getrandom(&mut secret).map_err(|e| anyhow!("could not generate a key pair: {}", e))?;
+        let secret = StaticSecret::from(secret);
+        let public = PublicKey::from(&secret);
+        Ok(KeyExchange { secret, public })
+    }
+
+    pub fn public_key(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.public.to_bytes()
+    }
+
+    /// key to wrap the SRTP keys between this side and the peer
+    pub fn wrapping_key(&self, peer_public_key: &[u8]) -> Result<[u8; 32]> {
+        let peer_public_key: [u8; PUBLIC_KEY_LENGTH] = peer_public_key.try_into()
+            .map_err(|_| anyhow!("public key needs {} bytes, got {}", PUBLIC_KEY_LENGTH, peer_public_key.len()))?;
+
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_public_key));
+        // a low order point of the peer would give a known shared secret
+        if !shared.was_contributory() {
+            return Err(anyhow!("invalid public key"));
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(shared.as_bytes()).expect("hmac accepts keys of any length");
+        mac.update(EXCHANGE_PURPOSE.as_bytes());
+        Ok(mac.finalize().into_bytes().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_sides_derive_the_same_key() {
+        let server = KeyExchange::new().unwrap();
+        let client = KeyExchange::new().unwrap();
+        let key = server.wrapping_key(&client.public_key()).unwrap();
+        assert_eq!(client.wrapping_key(&server.public_key()).unwrap(), key);
+
+        let other = KeyExchange::new().unwrap();
+        assert_ne!(other.wrapping_key(&server.public_key()).unwrap(), key);
+    }
+
+    #[test]
+    fn wrong_length() {
+        let exchange = KeyExchange::new().unwrap();
+        let public_key = KeyExchange::new().unwrap().public_key();
+        assert!(exchange.wrapping_key(&public_key[..PUBLIC_KEY_LENGTH - 1]).is_err());
+        assert!(exchange.wrapping_key(&[public_key.as_slice(), &[0]].concat()).is_err());
+        assert!(exchange.wrapping_key(&[]).is_err());
+    }
+
+    #[test]
+    fn low_order_point() {
+        let exchange = KeyExchange::new().unwrap();
+        assert!(exchange.wrapping_key(&[0u8; PUBLIC_KEY_LENGTH]).is_err());
+        // the point of order 1
+        let mut one = [0u8; PUBLIC_KEY_LENGTH];
+        one[0] = 1;
+        assert!(exchange.wrapping_key(&one).is_err());
+    }
+}
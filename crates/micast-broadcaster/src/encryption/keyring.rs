@@ -0,0 +1,399 @@
+/// SRTP master keys identified by their MKI, for the key rotation
+///
+/// the server generates the next key, distributes it to the clients and activates it afterwards.
+/// every packet is tagged with the MKI of its key, so the decoders accept all keys of the ring
+/// while the server and the clients switch. the key of the [`KeyProvider`] has the MKI 1 on all sides
+use std::fmt;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, Payload};
+use gst::prelude::*;
+use gst::glib;
+use log::{debug, info};
+use parking_lot::Mutex;
+
+use crate::services::auth;
+use super::keys::{KeyProvider, validate_key};
+use super::SrtpCipherSuite;
+
+/// length of the MKI in every packet
+pub const MKI_LENGTH: usize = 4;
+const INITIAL_MKI: u32 = 1;
+/// older keys are dropped if the ring has more keys
+const MAX_KEYS: usize = 3;
+const NONCE_LENGTH: usize = 12;
+const WRAP_PURPOSE: &str = "micast-srtp-key-wrap";
+
+/// A master key and its MKI
+#[derive(Clone, PartialEq)]
+pub struct SrtpKey {
+    pub mki: Vec<u8>,
+    pub key: Vec<u8>,
+}
+
+// never print the key
+impl fmt::Debug for SrtpKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SrtpKey {{ mki: {} }}", hex::encode(&self.mki))
+    }
+}
+
+fn mki(number: u32) -> Vec<u8> {
+    number.to_be_bytes().to_vec()
+}
+
+fn mki_number(mki: &[u8]) -> u32 {
+    mki.try_into().map(u32::from_be_bytes).unwrap_or(0)
+}
+
+struct Inner {
+    /// sorted by the MKI
+    keys: Vec<SrtpKey>,
+    current: Vec<u8>,
+    encoders: Vec<glib::WeakRef<gst::Element>>,
+    /// decoders and the ssrcs they requested keys for
+    decoders: Vec<(glib::WeakRef<gst::Element>, u32)>,
+}
+
+/// The keys of the server or a client
+pub struct KeyRing {
+    suite: SrtpCipherSuite,
+    inner: Mutex<Inner>,
+}
+
+impl fmt::Debug for KeyRing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.lock();
+        write!(f, "KeyRing {{ suite: {}, current: {}, keys: {} }}", self.suite, hex::encode(&inner.current), inner.keys.len())
+    }
+}
+
+impl KeyRing {
+    /// a ring with the initial key, all keys of the ring are for the crypto suite
+    pub fn new(key: Vec<u8>, suite: SrtpCipherSuite) -> Result<KeyRing> {
+        validate_key(&key, suite)?;
+        let initial = SrtpKey { mki: mki(INITIAL_MKI), key };
+
+        Ok(KeyRing {
+            suite,
+            inner: Mutex::new(Inner {
+                current: initial.mki.clone(),
+                keys: vec![initial],
+                encoders: Vec::new(),
+                decoders: Vec::new(),
+            }),
+        })
+    }
+
+    /// a ring with the key of the provider as initial key
+    pub fn from_provider(provider: &Arc<dyn KeyProvider>, suite: SrtpCipherSuite) -> Result<KeyRing> {
+        KeyRing::new(provider.key()?, suite)
+    }
+
+    pub fn suite(&self) -> SrtpCipherSuite {
+        self.suite
+    }
+
+    /// the key the encoders use
+    pub fn current(&self) -> SrtpKey {
+        let inner = self.inner.lock();
+        inner.keys.iter().find(|key| key.mki == inner.current).cloned().expect("current key is in the ring")
+    }
+
+    /// all keys, the oldest first
+    pub fn keys(&self) -> Vec<SrtpKey> {
+        self.inner.lock().keys.clone()
+    }
+
+    /// all keys except the key of the provider, the keys a new client needs
+    pub fn distributed_keys(&self) -> Vec<SrtpKey> {
+        self.inner.lock().keys.iter().filter(|key| mki_number(&key.mki) != INITIAL_MKI).cloned().collect()
+    }
+
+    pub fn key_for(&self, mki: &[u8]) -> Option<SrtpKey> {
+        self.inner.lock().keys.iter().find(|key| key.mki == mki).cloned()
+    }
+
+    /// the ring has only the key of the provider, no key was distributed
+    pub fn is_initial(&self) -> bool {
+        let inner = self.inner.lock();
+        inner.keys.len() == 1 && mki_number(&inner.current) == INITIAL_MKI
+    }
+
+    /// generate a random key with the next MKI, it is not used by the encoders until it gets activated
+    pub fn generate(&self) -> Result<SrtpKey> {
+        let mut key = vec![0u8; self.suite.key_length()];
+        getrandom::getrandom(&mut key).map_err(|e| anyhow!("could not generate a key: {}", e))?;
+
+        // rotations may run at the same time, so the MKI is taken and the key added under one lock
+        let mut inner = self.inner.lock();
+        let next = inner.keys.iter().map(|key| mki_number(&key.mki)).max().unwrap_or(0) + 1;
+        let key = SrtpKey { mki: mki(next), key };
+        let decoders = Self::push(&mut inner, key.clone());
+        drop(inner);
+
+        Self::request_keys(decoders);
+        Ok(key)
+    }
+
+    /// add a key to the ring, a key with the same MKI is replaced.
+    /// the decoders request their keys again, so they know the new key before the first packet uses it
+    pub fn insert(&self, key: SrtpKey) -> Result<()> {
+        validate_key(&key.key, self.suite)?;
+        if key.mki.len() != MKI_LENGTH {
+            return Err(anyhow!("mki needs {} bytes, got {}", MKI_LENGTH, key.mki.len()));
+        }
+
+        let decoders = Self::push(&mut self.inner.lock(), key);
+        Self::request_keys(decoders);
+        Ok(())
+    }
+
+    /// add the key to the locked ring, returns the decoders which have to request their keys again
+    fn push(inner: &mut Inner, key: SrtpKey) -> Vec<(glib::WeakRef<gst::Element>, u32)> {
+        debug!("add srtp key {:?}", key);
+        inner.keys.retain(|k| k.mki != key.mki);
+        inner.keys.push(key);
+        inner.keys.sort_by_key(|key| mki_number(&key.mki));
+        inner.decoders.drain(..).collect()
+    }
+
+    /// srtpdec emits request-key for the ssrc again with the next packet
+    fn request_keys(decoders: Vec<(glib::WeakRef<gst::Element>, u32)>) {
+        for (decoder, ssrc) in decoders {
+            if let Some(decoder) = decoder.upgrade() {
+                decoder.emit_by_name::<()>("remove-key", &[&ssrc]);
+            }
+        }
+    }
+
+    /// switch the encoders to the key with the MKI and drop the oldest keys
+    pub fn activate(&self, mki: &[u8]) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let key = inner.keys.iter().find(|key| key.mki == mki).cloned()
+            .ok_or_else(|| anyhow!("no srtp key with mki {}", hex::encode(mki)))?;
+
+        info!("activate srtp key {:?}", key);
+        inner.current = key.mki.clone();
+        while inner.keys.len() > MAX_KEYS {
+            let position = inner.keys.iter().position(|k| k.mki != key.mki).expect("more than one key");
+            inner.keys.remove(position);
+        }
+
+        inner.encoders.retain(|encoder| encoder.upgrade().is_some());
+        let encoders: Vec<_> = inner.encoders.iter().filter_map(|encoder| encoder.upgrade()).collect();
+        drop(inner);
+
+        for encoder in encoders {
+            set_encoder_key(&encoder, &key);
+        }
+        Ok(())
+    }
+
+    /// use the current key on the srtpenc and switch it on every activation
+    pub(crate) fn add_encoder(&self, srtpenc: &gst::Element) {
+        set_encoder_key(srtpenc, &self.current());
+        self.inner.lock().encoders.push(srtpenc.downgrade());
+    }
+
+    /// the srtpdec requested the keys for the ssrc
+    pub(crate) fn add_decoder(&self, srtpdec: &gst::Element, ssrc: u32) {
+        self.inner.lock().decoders.push((srtpdec.downgrade(), ssrc));
+    }
+
+    /// value of the SDP `a=crypto` attribute (RFC 4568) with the current key inline
+    pub(crate) fn crypto_attribute(&self) -> String {
+        let current = self.current();
+        format!("1 {} inline:{}|{}:{}", self.suite, BASE64_STANDARD.encode(&current.key), mki_number(&current.mki), MKI_LENGTH)
+    }
+
+    /// caps for srtpdec with the current key and all other keys of the ring, selected by the MKI of the packet
+    pub(crate) fn caps(&self, ssrc: Option<u32>) -> gst::Caps {
+        let current = self.current();
+        let others: Vec<_> = self.keys().into_iter()
+            .filter(|key| key.mki != current.mki)
+            .map(|key| gst::Structure::builder("key")
+                .field("srtp-key", gst::Buffer::from_slice(key.key))
+                .field("mki", gst::Buffer::from_slice(key.mki))
+                .build())
+            .collect();
+
+        let mut caps = gst::Caps::builder("application/x-srtp")
+            .field("srtp-key", gst::Buffer::from_slice(current.key))
+            .field("mki", gst::Buffer::from_slice(current.mki))
+            .field("srtp-cipher", self.suite.cipher())
+            .field("srtp-auth", self.suite.rtp_auth())
+            .field("srtcp-cipher", self.suite.cipher())
+            .field("srtcp-auth", self.suite.rtcp_auth());
+
+        if !others.is_empty() {
+            caps = caps.field("keys", gst::Array::new(others));
+        }
+
+        if let Some(ssrc) = ssrc {
+            caps = caps.field("ssrc", &ssrc);
+        }
+
+        caps.build()
+    }
+}
+
+fn set_encoder_key(srtpenc: &gst::Element, key: &SrtpKey) {
+    srtpenc.set_property("key", gst::Buffer::from_slice(key.key.clone()));
+    srtpenc.set_property("mki", gst::Buffer::from_slice(key.mki.clone()));
+}
+
+/// key to wrap distributed keys, derived from the shared secret of [`auth`]
+pub(crate) fn shared_wrapping_key() -> Result<[u8; 32]> {
+    auth::derive_key(WRAP_PURPOSE).ok_or_else(|| anyhow!("distributing srtp keys requires a shared secret"))
+}
+
+/// encrypt the key for the distribution, the MKI is authenticated too
+pub fn wrap_key(wrapping_key: &[u8; 32], key: &SrtpKey) -> Result<Vec<u8>> {
+    let mut nonce = [0u8; NONCE_LENGTH];
+    getrandom::getrandom(&mut nonce).map_err(|e| anyhow!("could not generate a nonce: {}", e))?;
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(wrapping_key));
+    let sealed = cipher.encrypt(Nonce::from_slice(&nonce), Payload { msg: &key.key, aad: &key.mki })
+        .map_err(|_| anyhow!("could not wrap the srtp key"))?;
+
+    Ok([&nonce[..], &sealed].concat())
+}
+
+/// decrypt a key wrapped by [`wrap_key`]
+pub fn unwrap_key(wrapping_key: &[u8; 32], mki: &[u8], wrapped: &[u8]) -> Result<SrtpKey> {
+    if wrapped.len() <= NONCE_LENGTH {
+        return Err(anyhow!("wrapped srtp key too short"));
+    }
+    let (nonce, sealed) = wrapped.split_at(NONCE_LENGTH);
+
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(wrapping_key));
+    let key = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: sealed, aad: mki })
+        .map_err(|_| anyhow!("could not unwrap the srtp key with mki {}", hex::encode(mki)))?;
+
+    Ok(SrtpKey { mki: mki.to_vec(), key })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring() -> KeyRing {
+        let suite = SrtpCipherSuite::default();
+        KeyRing::new(vec![7; suite.key_length()], suite).unwrap()
+    }
+
+    fn numbers(keys: &[SrtpKey]) -> Vec<u32> {
+        keys.iter().map(|key| mki_number(&key.mki)).collect()
+    }
+
+    #[test]
+    fn initial_key() {
+        let ring = ring();
+        assert!(ring.is_initial());
+        assert_eq!(mki_number(&ring.current().mki), INITIAL_MKI);
+        assert!(ring.distributed_keys().is_empty());
+        assert!(KeyRing::new(vec![7; 16], SrtpCipherSuite::default()).is_err());
+    }
+
+    #[test]
+    fn generated_mkis_increase() {
+        let ring = ring();
+        let keys: Vec<_> = (0..3).map(|_| ring.generate().unwrap()).collect();
+        assert_eq!(numbers(&keys), vec![2, 3, 4]);
+        assert!(keys.iter().all(|key| key.key.len() == ring.suite().key_length()));
+
+        // generated keys are not used before the activation
+        assert_eq!(mki_number(&ring.current().mki), INITIAL_MKI);
+        assert!(!ring.is_initial());
+        assert_eq!(numbers(&ring.keys()), vec![1, 2, 3, 4]);
+        assert_eq!(numbers(&ring.distributed_keys()), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn activation_trims_the_ring() {
+        let ring = ring();
+        let next = ring.generate().unwrap();
+        ring.activate(&next.mki).unwrap();
+        assert_eq!(ring.current(), next);
+        assert_eq!(numbers(&ring.keys()), vec![1, 2]);
+
+        ring.generate().unwrap();
+        let last = ring.generate().unwrap();
+        ring.activate(&last.mki).unwrap();
+        // the key of the provider is dropped first
+        assert_eq!(numbers(&ring.keys()), vec![2, 3, 4]);
+        assert_eq!(ring.current(), last);
+        assert!(ring.key_for(&mki(INITIAL_MKI)).is_none());
+    }
+
+    #[test]
+    fn activation_keeps_the_current_key() {
+        let ring = ring();
+        let keys: Vec<_> = (0..4).map(|_| ring.generate().unwrap()).collect();
+        ring.activate(&keys[0].mki).unwrap();
+        assert_eq!(numbers(&ring.keys()), vec![2, 4, 5]);
+        assert_eq!(ring.current(), keys[0]);
+    }
+
+    #[test]
+    fn activate_unknown_mki() {
+        let ring = ring();
+        assert!(ring.activate(&mki(2)).is_err());
+        assert_eq!(mki_number(&ring.current().mki), INITIAL_MKI);
+    }
+
+    #[test]
+    fn insert() {
+        let ring = ring();
+        let key = SrtpKey { mki: mki(5), key: vec![1; ring.suite().key_length()] };
+        ring.insert(key.clone()).unwrap();
+        assert_eq!(ring.key_for(&key.mki), Some(key.clone()));
+
+        // the same MKI replaces the key
+        let replaced = SrtpKey { mki: mki(5), key: vec![2; ring.suite().key_length()] };
+        ring.insert(replaced.clone()).unwrap();
+        assert_eq!(numbers(&ring.keys()), vec![1, 5]);
+        assert_eq!(ring.key_for(&key.mki), Some(replaced));
+
+        assert!(ring.insert(SrtpKey { mki: vec![0, 6], key: key.key.clone() }).is_err());
+        assert!(ring.insert(SrtpKey { mki: mki(6), key: vec![1; 16] }).is_err());
+        assert_eq!(numbers(&ring.keys()), vec![1, 5]);
+    }
+
+    #[test]
+    fn wrap_round_trip() {
+        let wrapping_key = [3u8; 32];
+        let key = SrtpKey { mki: mki(2), key: vec![9; 30] };
+        let wrapped = wrap_key(&wrapping_key, &key).unwrap();
+        assert_eq!(unwrap_key(&wrapping_key, &key.mki, &wrapped).unwrap(), key);
+
+        // a new nonce for every key
+        assert_ne!(wrap_key(&wrapping_key, &key).unwrap(), wrapped);
+        assert!(unwrap_key(&[4u8; 32], &key.mki, &wrapped).is_err());
+    }
+
+    #[test]
+    fn unwrap_fails_on_changes() {
+        let wrapping_key = [3u8; 32];
+        let key = SrtpKey { mki: mki(2), key: vec![9; 30] };
+        let wrapped = wrap_key(&wrapping_key, &key).unwrap();
+
+        let mut changed = wrapped.clone();
+        *changed.last_mut().unwrap() ^= 1;
+        assert!(unwrap_key(&wrapping_key, &key.mki, &changed).is_err());
+
+        let mut changed = wrapped.clone();
+        changed[NONCE_LENGTH] ^= 1;
+        assert!(unwrap_key(&wrapping_key, &key.mki, &changed).is_err());
+
+        // the MKI is authenticated
+        assert!(unwrap_key(&wrapping_key, &mki(3), &wrapped).is_err());
+
+        assert!(unwrap_key(&wrapping_key, &key.mki, &wrapped[..NONCE_LENGTH]).is_err());
+        assert!(unwrap_key(&wrapping_key, &key.mki, &[]).is_err());
+    }
+}
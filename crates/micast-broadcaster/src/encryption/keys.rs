@@ -0,0 +1,79 @@
+/// SRTP key material from the configuration of the deployment
+///
+/// keys are master key and master salt concatenated, as `srtpenc` and `srtpdec` expect them
+use std::fmt;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+
+use super::SrtpCipherSuite;
+
+/// environment variable read by [`EnvKey::default`]
+pub const DEFAULT_KEY_ENV: &str = "MICAST_SRTP_KEY";
+
+/// check that the key has the length the crypto suite requires
+pub fn validate_key(key: &[u8], suite: SrtpCipherSuite) -> Result<()> {
+    let expected = suite.key_length();
+    if key.len() != expected {
+        return Err(anyhow!("srtp key for {} needs {} bytes, got {}", suite, expected, key.len()));
+    }
+    Ok(())
+}
+
+/// Provides the SRTP master key to the `RTPServer` and the `PlaybackClient`
+pub trait KeyProvider: Send + Sync + fmt::Debug {
+    /// master key and salt
+    fn key(&self) -> Result<Vec<u8>>;
+}
+
+/// a key given by the application
+#[derive(Clone)]
+pub struct StaticKey(pub Vec<u8>);
+
+// never print the key
+impl fmt::Debug for StaticKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "StaticKey({} bytes)", self.0.len())
+    }
+}
+
+impl KeyProvider for StaticKey {
+    fn key(&self) -> Result<Vec<u8>> {
+        Ok(self.0.clone())
+    }
+}
+
+/// decode a hex encoded key, whitespace is ignored
+fn decode_hex(content: &str) -> Result<Vec<u8>> {
+    let hex: String = content.chars().filter(|c| !c.is_whitespace()).collect();
+    hex::decode(hex).map_err(|e| anyhow!("key is not hex encoded: {}", e))
+}
+
+/// a hex encoded key in a file, read on every request so a replaced file is used
+#[derive(Debug, Clone)]
+pub struct KeyFile(pub PathBuf);
+
+impl KeyProvider for KeyFile {
+    fn key(&self) -> Result<Vec<u8>> {
+        let content = std::fs::read_to_string(&self.0)
+            .map_err(|e| anyhow!("could not read key file {}: {}", self.0.display(), e))?;
+        decode_hex(&content)
+    }
+}
+
+/// a hex encoded key in an environment variable
+#[derive(Debug, Clone)]
+pub struct EnvKey(pub String);
+
+impl Default for EnvKey {
+    fn default() -> Self {
+        EnvKey(DEFAULT_KEY_ENV.to_string())
+    }
+}
+
+impl KeyProvider for EnvKey {
+    fn key(&self) -> Result<Vec<u8>> {
+        let content = std::env::var(&self.0).map_err(|_| anyhow!("environment variable {} is not set", self.0))?;
+        decode_hex(&content)
+    }
+}
@@ -1,12 +1,64 @@
 /// Encryption Callbacks for RTP RTCP 
 /// 
-/// 
+/// the keys come from a [`KeyRing`], initialised with the key of a [`KeyProvider`] configured for the deployment
+use std::sync::Arc;
+
 use gst::prelude::*;
 use gst::glib;
-use anyhow::{Result};
+use anyhow::{anyhow, Result};
 use log::debug;
 use crate::helpers::make_element;
 
+pub mod keys;
+pub mod keyring;
+pub mod exchange;
+pub mod suite;
+
+pub use keys::{KeyProvider, StaticKey, KeyFile, EnvKey, validate_key};
+pub use keyring::{KeyRing, SrtpKey};
+pub use exchange::KeyExchange;
+pub use suite::SrtpCipherSuite;
+
+/// Which streams a `PlaybackClient` accepts
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum EncryptionPolicy {
+    /// only unencrypted streams
+    #[default]
+    Disabled,
+    /// encrypted and unencrypted streams, requires a key provider
+    Optional,
+    /// only encrypted streams, requires a key provider
+    Required,
+}
+
+impl EncryptionPolicy {
+    /// the policy accepts a stream with this encryption
+    pub fn accepts(&self, encrypted: bool) -> bool {
+        match self {
+            EncryptionPolicy::Disabled => !encrypted,
+            EncryptionPolicy::Optional => true,
+            EncryptionPolicy::Required => encrypted,
+        }
+    }
+
+    /// whether the stream gets decrypted
+    ///
+    /// # Arguments
+    /// * `encrypted` - encryption of the stream if it is known, e.g. from the announcement of the server
+    pub fn decrypt(&self, encrypted: Option<bool>) -> Result<bool> {
+        match encrypted {
+            Some(encrypted) if !self.accepts(encrypted) => Err(anyhow!(
+                "the {} stream is refused by the encryption policy {:?}",
+                if encrypted { "encrypted" } else { "unencrypted" },
+                self
+            )),
+            Some(encrypted) => Ok(encrypted),
+            // without an announcement the stream is expected to be encrypted if the client can decrypt it
+            None => Ok(*self != EncryptionPolicy::Disabled),
+        }
+    }
+}
+
 
 #[derive(Clone)]
 enum DeOrEncoder {
@@ -51,59 +103,22 @@ impl RTPorRTCP {
 }
 
 
-pub fn encryption_cap(ssrc: Option<u32>) -> Result<gst::Caps> {
-    let (key, _mki) = get_key_and_mki();
-
-    //channels=(int)2,format=(string)S16LE,media=(string)audio,payload=(int)96,clock-rate=(int)44100,encoding-name=(string)L24
-    let mut caps = gst::Caps::builder("application/x-srtp")
-        .field("srtp-key", &key)
-        .field("srtp-cipher", &"aes-128-icm")
-        .field("srtp-auth", &"hmac-sha1-80")
-        .field("srtcp-cipher", &"aes-128-icm")
-        .field("srtcp-auth", &"hmac-sha1-80");
-
-
-    if let Some(ssrc) = ssrc {
-        caps = caps.field("ssrc", &ssrc);
-    }
-
-    
-    let caps = caps.build();
-
-    Ok(caps)
-}
-
-fn get_key_and_mki() -> (gst::Buffer, gst::Buffer) {
-    let key = {
-        let cl = crate::RTP_KEY.clone();
-        let arr = cl.as_bytes();
-        let part = Vec::from_iter(arr[5..35].iter().cloned());
-        let buff = gst::Buffer::from_slice(part);
-        buff
-    };
-
-    let mki = {
-        let cl = crate::RTP_MKI.clone();
-        let arr = cl.as_bytes();
-        let part = Vec::from_iter(arr[5..35].iter().cloned());
-        let buff = gst::Buffer::from_slice(part);
-        buff
-    };
-
-    (key, mki)
+/// caps for srtpdec with all keys of the ring
+pub fn encryption_cap(keyring: &KeyRing, ssrc: Option<u32>) -> Result<gst::Caps> {
+    Ok(keyring.caps(ssrc))
 }
 
-pub fn client_encryption(rtpbin: &gst::Element) -> Result<()> {
+pub fn client_encryption(rtpbin: &gst::Element, keyring: &Arc<KeyRing>) -> Result<()> {
     let all_signals = vec![
         (RTPorRTCP::Rtp, DeOrEncoder::Decoder),
         (RTPorRTCP::Rtcp,  DeOrEncoder::Encoder),
         (RTPorRTCP::Rtcp,  DeOrEncoder::Decoder),
     ];
 
-    encrypt_bin(&rtpbin, all_signals)
+    encrypt_bin(&rtpbin, keyring, all_signals)
 }
 
-pub fn server_encryption(rtpbin: &gst::Element) -> Result<()> {
+pub fn server_encryption(rtpbin: &gst::Element, keyring: &Arc<KeyRing>) -> Result<()> {
 
     let all_signals = vec![
         (RTPorRTCP::Rtp, DeOrEncoder::Encoder),
@@ -112,17 +127,15 @@ pub fn server_encryption(rtpbin: &gst::Element) -> Result<()> {
         (RTPorRTCP::Rtcp,  DeOrEncoder::Decoder),
     ];
 
-    encrypt_bin(&rtpbin, all_signals)
+    encrypt_bin(&rtpbin, keyring, all_signals)
 }
 
-fn encrypt_bin(rtpbin: &gst::Element, all_signals: Vec<(RTPorRTCP, DeOrEncoder)>) -> Result<()> {
+fn encrypt_bin(rtpbin: &gst::Element, keyring: &Arc<KeyRing>, all_signals: Vec<(RTPorRTCP, DeOrEncoder)>) -> Result<()> {
+
 
-    let (key, mki) = get_key_and_mki();
-    
     for (signal_type, coder_type) in all_signals {
 
-        let key_cloned = key.clone();
-        let mki_cloned = mki.clone();
+        let keyring_cloned = keyring.clone();
         let signal_type_cloned = signal_type.clone();
         let coder_type_cloned = coder_type.clone();
 
@@ -136,14 +149,14 @@ fn encrypt_bin(rtpbin: &gst::Element, all_signals: Vec<(RTPorRTCP, DeOrEncoder)>
             let session:u32 = vars[1].get().unwrap_or(0);
             debug!("setup an {} for session {}", signal_cloned, session);
 
-            Some(callback(&signal_type_cloned, &coder_type_cloned, &key_cloned, &mki_cloned, &session).expect("this should never fail!"))
+            Some(callback(&signal_type_cloned, &coder_type_cloned, &keyring_cloned, &session).expect("this should never fail!"))
         });
     }
     
     Ok(())
 } 
 
-fn callback(pre: &RTPorRTCP, deoren: &DeOrEncoder, key: &gst::Buffer, mki: &gst::Buffer, session: &u32 ) -> Result<glib::Value> {
+fn callback(pre: &RTPorRTCP, deoren: &DeOrEncoder, keyring: &Arc<KeyRing>, session: &u32 ) -> Result<glib::Value> {
 
     let element = match deoren {
         DeOrEncoder::Encoder => {
@@ -157,14 +170,15 @@ fn callback(pre: &RTPorRTCP, deoren: &DeOrEncoder, key: &gst::Buffer, mki: &gst:
 
             debug!("request pad for {} {}", pre.clone().to_string(), name );
             element.request_pad_simple(&name);
-            element.set_property_from_str("rtp-cipher", &"aes-128-icm" );
-            element.set_property_from_str("rtp-auth", &"hmac-sha1-80" );
-            element.set_property_from_str("rtcp-cipher", &"aes-128-icm" );
-            element.set_property_from_str("rtcp-auth", &"hmac-sha1-80" );
+            let suite = keyring.suite();
+            element.set_property_from_str("rtp-cipher", suite.cipher() );
+            element.set_property_from_str("rtp-auth", suite.rtp_auth() );
+            element.set_property_from_str("rtcp-cipher", suite.cipher() );
+            element.set_property_from_str("rtcp-auth", suite.rtcp_auth() );
 
-            element.set_property("key", key );
+            // key and mki, switched on every key rotation
+            keyring.add_encoder(&element);
 
-            //element.try_set_property("mki", mki )?;
             element
         }
         DeOrEncoder::Decoder => {
@@ -173,11 +187,9 @@ fn callback(pre: &RTPorRTCP, deoren: &DeOrEncoder, key: &gst::Buffer, mki: &gst:
                 , None
             )?;
 
-            element.connect("request-key", false, |vars| request_key_callback(vars[0].get().unwrap(), vars[1].get().unwrap()));
+            let keyring = keyring.clone();
+            element.connect("request-key", false, move |vars| request_key_callback(vars[0].get().unwrap(), vars[1].get().unwrap(), &keyring));
 
-        
-            //element.try_set_property("key", key )?;
-            //element.try_set_property("mki", mki )?;
             element
         }
     };
@@ -186,11 +198,13 @@ fn callback(pre: &RTPorRTCP, deoren: &DeOrEncoder, key: &gst::Buffer, mki: &gst:
     Ok(element.to_value())
 }
 
-fn request_key_callback(_srtpdec: &gst::Element, ssrc: u32) -> Option<glib::Value> {
+fn request_key_callback(srtpdec: &gst::Element, ssrc: u32, keyring: &KeyRing) -> Option<glib::Value> {
 
     debug!("SSRC {} request an KEY so we generate caps for it", ssrc);
 
-    let caps = encryption_cap(Some(ssrc)).unwrap();
+    // the key is removed again on the next key change, so srtpdec requests the new keys
+    keyring.add_decoder(srtpdec, ssrc);
+    let caps = encryption_cap(keyring, Some(ssrc)).unwrap();
 
     Some(caps.to_value())
 }
\ No newline at end of file
@@ -0,0 +1,161 @@
+/// SRTP crypto suites supported by `srtpenc` and `srtpdec`
+///
+/// names are the ones of the SDP `a=crypto` attribute (RFC 4568, RFC 7714)
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use gst::glib;
+use gst::prelude::*;
+
+use crate::helpers::make_element;
+
+/// Cipher and authentication of SRTP and SRTCP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SrtpCipherSuite {
+    #[default]
+    AesCm128HmacSha1_80,
+    /// shorter authentication tag of the RTP packets, SRTCP keeps the 80 bit tag
+    AesCm128HmacSha1_32,
+    AesCm256HmacSha1_80,
+    AesCm256HmacSha1_32,
+    /// authenticated encryption, requires a `srtpenc` built with GCM support
+    AeadAes128Gcm,
+    AeadAes256Gcm,
+}
+
+impl SrtpCipherSuite {
+    pub const ALL: [SrtpCipherSuite; 6] = [
+        SrtpCipherSuite::AesCm128HmacSha1_80,
+        SrtpCipherSuite::AesCm128HmacSha1_32,
+        SrtpCipherSuite::AesCm256HmacSha1_80,
+        SrtpCipherSuite::AesCm256HmacSha1_32,
+        SrtpCipherSuite::AeadAes128Gcm,
+        SrtpCipherSuite::AeadAes256Gcm,
+    ];
+
+    /// name in the SDP and the discovery announcement
+    pub fn name(&self) -> &'static str {
+        match self {
+            SrtpCipherSuite::AesCm128HmacSha1_80 => "AES_CM_128_HMAC_SHA1_80",
+            SrtpCipherSuite::AesCm128HmacSha1_32 => "AES_CM_128_HMAC_SHA1_32",
+            SrtpCipherSuite::AesCm256HmacSha1_80 => "AES_256_CM_HMAC_SHA1_80",
+            SrtpCipherSuite::AesCm256HmacSha1_32 => "AES_256_CM_HMAC_SHA1_32",
+            SrtpCipherSuite::AeadAes128Gcm => "AEAD_AES_128_GCM",
+            SrtpCipherSuite::AeadAes256Gcm => "AEAD_AES_256_GCM",
+        }
+    }
+
+    /// value of the `rtp-cipher` and `rtcp-cipher` properties
+    pub fn cipher(&self) -> &'static str {
+        match self {
+            SrtpCipherSuite::AesCm128HmacSha1_80 | SrtpCipherSuite::AesCm128HmacSha1_32 => "aes-128-icm",
+            SrtpCipherSuite::AesCm256HmacSha1_80 | SrtpCipherSuite::AesCm256HmacSha1_32 => "aes-256-icm",
+            SrtpCipherSuite::AeadAes128Gcm => "aes-128-gcm",
+            SrtpCipherSuite::AeadAes256Gcm => "aes-256-gcm",
+        }
+    }
+
+    /// value of the `rtp-auth` property, GCM authenticates itself
+    pub fn rtp_auth(&self) -> &'static str {
+        match self {
+            SrtpCipherSuite::AesCm128HmacSha1_80 | SrtpCipherSuite::AesCm256HmacSha1_80 => "hmac-sha1-80",
+            SrtpCipherSuite::AesCm128HmacSha1_32 | SrtpCipherSuite::AesCm256HmacSha1_32 => "hmac-sha1-32",
+            SrtpCipherSuite::AeadAes128Gcm | SrtpCipherSuite::AeadAes256Gcm => "null",
+        }
+    }
+
+    /// value of the `rtcp-auth` property, SRTCP always uses the 80 bit tag (RFC 3711)
+    pub fn rtcp_auth(&self) -> &'static str {
+        match self {
+            SrtpCipherSuite::AeadAes128Gcm | SrtpCipherSuite::AeadAes256Gcm => "null",
+            _ => "hmac-sha1-80",
+        }
+    }
+
+    /// length of master key and salt
+    pub fn key_length(&self) -> usize {
+        match self {
+            SrtpCipherSuite::AesCm128HmacSha1_80 | SrtpCipherSuite::AesCm128HmacSha1_32 => 30,
+            SrtpCipherSuite::AesCm256HmacSha1_80 | SrtpCipherSuite::AesCm256HmacSha1_32 => 46,
+            SrtpCipherSuite::AeadAes128Gcm => 28,
+            SrtpCipherSuite::AeadAes256Gcm => 44,
+        }
+    }
+
+    /// the installed `srtpenc` knows the cipher and the authentication
+    pub fn is_available(&self) -> bool {
+        let srtpenc = match make_element("srtpenc", None) {
+            Ok(srtpenc) => srtpenc,
+            Err(_) => return false,
+        };
+
+        let has_value = |property: &str, nick: &str| {
+            srtpenc.find_property(property)
+                .and_then(|pspec| glib::EnumClass::new(pspec.value_type()))
+                .map(|class| class.value_by_nick(nick).is_some())
+                .unwrap_or(false)
+        };
+
+        has_value("rtp-cipher", self.cipher()) && has_value("rtp-auth", self.rtp_auth()) && has_value("rtcp-auth", self.rtcp_auth())
+    }
+
+    /// all suites the installed `srtpenc` supports
+    pub fn available() -> Vec<SrtpCipherSuite> {
+        Self::ALL.into_iter().filter(|suite| suite.is_available()).collect()
+    }
+}
+
+impl fmt::Display for SrtpCipherSuite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for SrtpCipherSuite {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL.into_iter()
+            .find(|suite| suite.name() == s)
+            .ok_or_else(|| anyhow!("unknown srtp crypto suite {}", s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_round_trip() {
+        for suite in SrtpCipherSuite::ALL {
+            assert_eq!(suite.to_string().parse::<SrtpCipherSuite>().unwrap(), suite);
+        }
+        assert_eq!(SrtpCipherSuite::default().name(), "AES_CM_128_HMAC_SHA1_80");
+    }
+
+    #[test]
+    fn unknown_suites() {
+        assert!("".parse::<SrtpCipherSuite>().is_err());
+        assert!("aes_cm_128_hmac_sha1_80".parse::<SrtpCipherSuite>().is_err());
+        assert!("AEAD_CHACHA20_POLY1305".parse::<SrtpCipherSuite>().is_err());
+    }
+
+    #[test]
+    fn key_lengths() {
+        for suite in SrtpCipherSuite::ALL {
+            // master key of the cipher, 112 bit salt for AES-CM (RFC 3711), 96 bit for GCM (RFC 7714)
+            let key = if suite.cipher().starts_with("aes-128") { 16 } else { 32 };
+            let salt = if suite.cipher().ends_with("gcm") { 12 } else { 14 };
+            assert_eq!(suite.key_length(), key + salt, "{}", suite);
+        }
+    }
+
+    #[test]
+    fn srtcp_keeps_the_80_bit_tag() {
+        assert_eq!(SrtpCipherSuite::AesCm128HmacSha1_32.rtp_auth(), "hmac-sha1-32");
+        assert_eq!(SrtpCipherSuite::AesCm128HmacSha1_32.rtcp_auth(), "hmac-sha1-80");
+        assert_eq!(SrtpCipherSuite::AeadAes256Gcm.rtp_auth(), "null");
+        assert_eq!(SrtpCipherSuite::AeadAes256Gcm.rtcp_auth(), "null");
+    }
+}
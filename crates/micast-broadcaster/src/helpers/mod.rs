@@ -35,3 +35,41 @@ pub fn make_element(
         }
     }
 }
+
+
+/// creates the aux bin for rtpbin `request-aux-sender` / `request-aux-receiver`
+/// 
+/// the bin contains a `rtprtxsend` or `rtprtxreceive` element named `{name}{session}`
+/// and maps the audio payload type to the retransmission payload type
+pub(crate) fn make_rtx_bin(
+    factory_name: &'static str,
+    name: &str,
+    session: u32,
+) -> Result<gst::Bin, anyhow::Error> {
+    use gst::prelude::*;
+    use crate::rtpserver::{PAYLOAD_TYPE, RTX_PAYLOAD_TYPE};
+
+    let bin = gst::Bin::new(None);
+    let rtx = make_element(factory_name, Some(&format!("{}{}", name, session)))?;
+
+    let pt_map = gst::Structure::builder("application/x-rtp-pt-map")
+        .field(&PAYLOAD_TYPE.to_string(), RTX_PAYLOAD_TYPE)
+        .build();
+    rtx.set_property("payload-type-map", &pt_map);
+
+    bin.add(&rtx)?;
+
+    let sink_pad = gst::GhostPad::with_target(
+        Some(&format!("sink_{}", session)), 
+        &rtx.static_pad("sink").unwrap()
+    )?;
+    bin.add_pad(&sink_pad)?;
+
+    let src_pad = gst::GhostPad::with_target(
+        Some(&format!("src_{}", session)), 
+        &rtx.static_pad("src").unwrap()
+    )?;
+    bin.add_pad(&src_pad)?;
+
+    Ok(bin)
+}
@@ -1,20 +1,24 @@
 //#![feature(const_convert)]
 
-pub(crate) const RTP_KEY: &str = "disou§fH398zJKMh&ufvgoih2u9hgsduihf!/&§$(§())2389dsiub;GT_Z2DFG9h=83F98h";
-pub(crate) const RTP_MKI: &str = "fhiuh/289hsdHRDhsdiuh)nv2938hv9fhiuhsdgh28793h&/z7hf27893h87h87v7654rScx";
-
 pub mod services;
 mod helpers;
 mod player;
-mod encryption;
-//pub mod rtspserver;
+pub mod encryption;
+pub mod rtspserver;
 pub mod rtpserver;
+pub mod sdp;
+pub mod aes67;
+pub mod clock;
+pub mod qos;
 
 pub mod broadcast;
 pub mod output;
 
 
 pub use player::PlaybackClient;
+pub use player::PlaybackClientOptions;
+pub use player::FecStats;
+pub use player::PathStats;
 pub use player::local_player::LocalPlayer;
 //pub use player::rtsp;
 pub use broadcast::Broadcast;
@@ -1,5 +1,7 @@
 pub(crate) mod local_player;
 
+use std::collections::HashMap;
+use std::net::{IpAddr, ToSocketAddrs};
 use std::str::FromStr;
 use std::sync::atomic::{Ordering, AtomicBool};
 use std::sync::{Arc, Weak};
@@ -12,27 +14,256 @@ use gst::prelude::*;
 use gst::glib;
 use log::{debug,warn, info, trace};
 
-use crate::helpers::{make_element, upgrade_weak};
+use crate::helpers::{make_element, make_rtx_bin, upgrade_weak};
 use crate::sleep_ms;
 use crate::services;
+use crate::qos::{self, DscpConfig};
+use crate::rtpserver::{RetransmissionStats, RedundancyOptions, FEC_PAYLOAD_TYPE, PAYLOAD_TYPE, RTX_PAYLOAD_TYPE};
+use crate::aes67::Aes67Options;
+use crate::sdp::MediaFormat;
+use crate::clock::{self, ClockSource, SyncStatus};
+use crate::services::sap::SapSession;
+use crate::services::discovery::{self, Announcement, DiscoveryMethod, ServerSelection};
+use crate::services::control::{ControlCommand, ControlListener, CONTROL_PORT};
+use crate::services::report::ClientReport;
+use crate::services::access::Credential;
+use crate::encryption::{self, EncryptionPolicy, KeyExchange, KeyProvider, KeyRing, SrtpCipherSuite};
+use crate::encryption::keyring::{shared_wrapping_key, unwrap_key};
 
 /// Default latency for Playback
 const LATENCY:i32 = 1500;
 
-#[allow(unused)]
-const ENCRYPTION_ENABLED:bool = false;
 const DEFAULT_AUDIO_RATE:i32 = 48000;
+// errors kept for the report to the server
+const MAX_RECENT_ERRORS:usize = 5;
+
+
+/// Options for the Playback Client
+#[derive(Debug, Clone, Default)]
+pub struct PlaybackClientOptions {
+    /// request lost packets from the server via RTCP NACK (RFC 4588),
+    /// the server has to enable retransmission too
+    pub retransmission: bool,
+    /// recover lost packets from ULPFEC packets send by the server
+    pub fec: bool,
+    /// receive an AES67 stream from a multicast group synced by a PTP clock,
+    /// works with the `RTPServer` in AES67 mode and other AES67 senders
+    pub aes67: Option<Aes67Options>,
+    /// clock to sync with the broadcaster, has to be the same as on the broadcaster.
+    /// in AES67 mode the PTP clock of the AES67 options is always used
+    pub clock: ClockSource,
+    /// receive the stream over a second path and merge both by sequence number,
+    /// the server has to enable redundancy with the same secondary port and multicast group
+    pub redundancy: Option<RedundancyOptions>,
+    /// interfaces where the client listens for server announcements
+    pub interfaces: services::InterfaceFilter,
+    /// how the client searches for the server if the server address is 0.0.0.0
+    pub discovery: DiscoveryMethod,
+    /// choose among several servers instead of using the first one which announces itself
+    pub server_selection: Option<ServerSelection>,
+    /// ordered list of known servers, probed over unicast before the discovery.
+    /// the first server which answers is used, also on reconnect if the current one stops answering.
+    /// can be loaded from a file with [`services::load_server_list`]
+    pub static_servers: Vec<String>,
+    /// listen for commands of the server on the control port, see [`services::control`].
+    /// requires a shared secret, see [`services::auth`], commands are only accepted from the current server.
+    /// clients of an encrypted stream always listen for the SRTP keys of the server
+    pub remote_control: bool,
+    /// port of the control listener, per default [`CONTROL_PORT`], 0 for a free port.
+    /// the port is sent to the server with the confirmations, so several clients on one host can be controlled
+    pub control_port: Option<u16>,
+    /// SRTP key of the deployment, has to be the same key as on the server
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    /// which streams are accepted, the encryption of the stream is taken from the announcement
+    /// of the server. without announcement the stream is expected to be encrypted unless the policy is disabled
+    pub encryption: EncryptionPolicy,
+    /// SRTP crypto suite of a server without announcement, otherwise the announced suite is used
+    pub cipher_suite: SrtpCipherSuite,
+    /// token presented in every confirmation, for servers with a token store. only a proof of the token is sent
+    pub credential: Option<Credential>,
+    /// DSCP marking of the RTCP, clock, confirmation and control packets, the client sends no RTP
+    pub dscp: DscpConfig,
+}
+
+/// Counters of the ULPFEC decoder
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FecStats {
+    /// number of packets recovered from FEC packets
+    pub recovered: u32,
+    /// number of lost packets which could not be recovered
+    pub unrecovered: u32,
+}
+
+/// Counters of one receive path
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathStats {
+    /// number of RTP packets received on this path
+    pub received: u64,
+    /// number of RTP packets missing on this path, detected by gaps in the sequence numbers
+    pub lost: u64,
+}
+
+/// Counts the received and lost packets of a path by the RTP sequence numbers
+#[derive(Default)]
+struct PathCounter {
+    stats: PathStats,
+    last_seq: Option<u16>,
+    /// the packets received on this path, to tell late packets from duplicates
+    seen: Deduplicator,
+}
+
+impl PathCounter {
+    fn count(&mut self, ssrc: u32, seq: u16) {
+        self.stats.received += 1;
+        if self.seen.ssrc != Some(ssrc) {
+            self.last_seq = None;
+        }
+        if !self.seen.first(ssrc, seq) {
+            // duplicate, or too late to tell
+            return;
+        }
+
+        if let Some(last_seq) = self.last_seq {
+            let diff = seq.wrapping_sub(last_seq);
+            if diff >= 0x8000 {
+                // reordered packet, it was counted as lost when the gap opened
+                self.stats.lost = self.stats.lost.saturating_sub(1);
+                return;
+            }
+            self.stats.lost += (diff - 1) as u64;
+        }
+        self.last_seq = Some(seq);
+    }
+}
+
+/// Add a probe to the src pad of the udpsrc which counts the packets of the path
+fn count_path(pipeline: &gst::Pipeline, name: &str) -> Option<Arc<Mutex<PathCounter>>> {
+    let pad = pipeline.by_name(name)?.static_pad("src")?;
+    let counter = Arc::new(Mutex::new(PathCounter::default()));
+
+    let probe_counter = counter.clone();
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(buffer)) = &info.data {
+            if let Ok(rtp_buffer) = gst_rtp::RTPBuffer::from_buffer_readable(buffer) {
+                probe_counter.lock().count(rtp_buffer.ssrc(), rtp_buffer.seq());
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
 
+    Some(counter)
+}
+
+/// number of sequence numbers behind the highest one which are deduplicated, 1 s of AES67 packets
+const DEDUPLICATION_WINDOW: u16 = 1024;
+
+/// Drops the packets which already arrived over the other path, by the RTP sequence number
+///
+/// packets older than the [`DEDUPLICATION_WINDOW`] are dropped too, they are too late for the jitterbuffer
+#[derive(Default)]
+struct Deduplicator {
+    ssrc: Option<u32>,
+    /// highest forwarded sequence number
+    highest: u16,
+    /// forwarded sequence numbers of the window, bit `seq % DEDUPLICATION_WINDOW`
+    seen: [u64; DEDUPLICATION_WINDOW as usize / 64],
+}
+
+impl Deduplicator {
+    /// the packet was not forwarded yet
+    fn first(&mut self, ssrc: u32, seq: u16) -> bool {
+        if self.ssrc != Some(ssrc) {
+            // a new stream
+            self.ssrc = Some(ssrc);
+            self.highest = seq;
+            self.seen = Default::default();
+            self.mark(seq);
+            return true;
+        }
+
+        let diff = seq.wrapping_sub(self.highest);
+        if diff == 0 {
+            return false;
+        }
+        if diff < 0x8000 {
+            // newer packet, the sequence numbers up to it were not forwarded yet
+            if diff >= DEDUPLICATION_WINDOW {
+                self.seen = Default::default();
+            } else {
+                for skipped in 1..diff {
+                    self.unmark(self.highest.wrapping_add(skipped));
+                }
+            }
+            self.highest = seq;
+            self.mark(seq);
+            return true;
+        }
+
+        if self.highest.wrapping_sub(seq) >= DEDUPLICATION_WINDOW || self.is_marked(seq) {
+            return false;
+        }
+        self.mark(seq);
+        true
+    }
+
+    fn position(seq: u16) -> (usize, u64) {
+        let bit = (seq % DEDUPLICATION_WINDOW) as usize;
+        (bit / 64, 1 << (bit % 64))
+    }
+
+    fn is_marked(&self, seq: u16) -> bool {
+        let (index, mask) = Self::position(seq);
+        self.seen[index] & mask != 0
+    }
+
+    fn mark(&mut self, seq: u16) {
+        let (index, mask) = Self::position(seq);
+        self.seen[index] |= mask;
+    }
+
+    fn unmark(&mut self, seq: u16) {
+        let (index, mask) = Self::position(seq);
+        self.seen[index] &= !mask;
+    }
+}
+
+/// Add a probe to the src pad of the element which drops the packets already received over the other path
+fn deduplicate(element: &gst::Element) -> Result<(), anyhow::Error> {
+    let pad = element.static_pad("src").ok_or_else(|| anyhow!("{} has no src pad", element.name()))?;
+    let deduplicator = Mutex::new(Deduplicator::default());
+
+    pad.add_probe(gst::PadProbeType::BUFFER, move |_, info| {
+        if let Some(gst::PadProbeData::Buffer(buffer)) = &info.data {
+            if let Ok(rtp_buffer) = gst_rtp::RTPBuffer::from_buffer_readable(buffer) {
+                if !deduplicator.lock().first(rtp_buffer.ssrc(), rtp_buffer.seq()) {
+                    trace!("drop duplicate packet {}", rtp_buffer.seq());
+                    return gst::PadProbeReturn::Drop;
+                }
+            }
+        }
+        gst::PadProbeReturn::Ok
+    });
+    Ok(())
+}
 
 struct State {
     #[allow(unused)]
     rtpbin: gst::Element,
+    clock: gst::Clock,
     source: gst::Element,
     audio_in_src: gst::Pad,
     recv_rtp_src: Option<gst::Pad>,
+    /// depayloader and decoder bin linked to `convert`, with the encoding name it decodes
+    decoder: Option<(String, gst::Element)>,
     current_output_device: String,
     current_output_element: String,
     sender_clock_address: String,
+    /// address of the current server, also if it was discovered. commands are only accepted from it
+    server_address: String,
+    // from the statistics of the net client clock
+    rtt: Option<gst::ClockTime>,
+    clock_offset: Option<i64>,
+    recent_errors: std::collections::VecDeque<String>,
 }
 
 #[derive(Clone)]
@@ -57,14 +288,23 @@ impl PlaybackClientWeak {
 /// Simple Playback Client for Playback RTP Server Stream
 pub struct PlaybackClientInner {
     pub pipeline: gst::Pipeline,
-    #[allow(unused)]
     convert: gst::Element,
-    #[allow(unused)]
-    rtpdepayload: gst::Element,
+    /// caps of the payload types for the `request-pt-map` of the rtpbin
+    payload_formats: Mutex<HashMap<u32, gst::Caps>>,
+    /// serializes the changes of the decoder in the streaming threads, never locked with the state
+    decoder_change: Mutex<()>,
     #[allow(unused)]
     audio_rate: i32,
     
     timeout_error_handling_is_active: AtomicBool,
+    options: PlaybackClientOptions,
+    path_counters: Vec<Arc<Mutex<PathCounter>>>,
+    #[allow(dead_code)]
+    control: Mutex<Option<ControlListener>>,
+    keyring: Option<Arc<KeyRing>>,
+    key_exchange: Option<KeyExchange>,
+    /// the stream is decrypted
+    encrypted: bool,
     state: Arc<Mutex<State>>,
     //last_broadcast: Arc<Mutex<Option<Instant>>>,
 }
@@ -89,7 +329,8 @@ impl PlaybackClient {
     ///                normaly is 5000 to send RTP, 
     ///                5001 to send server RTCP, 
     ///                5002 to receiver RTCP to the server
-    /// * `clock_port` - port where the NTP Server ist listen on per default 8555
+    /// * `clock_port` - port where the NTP Server ist listen on per default 8555, 
+    ///                  only used for [`ClockSource::NetTime`]
     /// * `audio_rate` - audio rate of the stream per default 44100
     /// * `latency` - latency of the stream per default 700
     pub fn new(
@@ -101,9 +342,44 @@ impl PlaybackClient {
         audio_device: Option<String>,
         //existing_clock: Option<gst_net::NetClientClock>,
     ) -> Result<PlaybackClient, anyhow::Error> {
+        Self::new_with_options(
+            server_address, 
+            rtp_port, 
+            clock_port, 
+            audio_rate, 
+            latency, 
+            audio_device, 
+            PlaybackClientOptions::default()
+        )
+    }
+
+    /// Create a Playback Client with additional options
+    /// 
+    /// see [`PlaybackClient::new`] for the arguments
+    /// * `options` - additional options, see [`PlaybackClientOptions`]
+    pub fn new_with_options(
+        server_address: &str,
+        rtp_port: i32,
+        clock_port: Option<i32>,
+        audio_rate: Option<i32>,
+        latency: Option<i32>,
+        audio_device: Option<String>,
+        mut options: PlaybackClientOptions,
+    ) -> Result<PlaybackClient, anyhow::Error> {
 
         gst::init()?;
 
+        // without signatures every host could send commands as the server
+        if options.remote_control && !services::auth::is_enabled() {
+            return Err(anyhow!("remote control requires a shared secret"));
+        }
+
+        options.clock = match (&options.aes67, &options.clock) {
+            (Some(aes67), _) => aes67.clock_source(),
+            (None, ClockSource::NetTime { port }) => ClockSource::NetTime { port: clock_port.unwrap_or(*port) },
+            (None, source) => source.clone(),
+        };
+
         debug!("init playback client");
 
         let re_server_address = if server_address == "0.0.0.0" {
@@ -113,21 +389,55 @@ impl PlaybackClient {
         };
 
         // this function only search via broadcast for an ip if required (rtp_receiver_address == 0.0.0.0)
-        let clock_rtcp_server_address = if re_server_address.is_none() {
-            let remote_address = Self::search_for_ip(
+        let (clock_rtcp_server_address, announcement) = if options.aes67.is_some() {
+            // aes67 streams are announced by SAP and not confirmed
+            (re_server_address.unwrap_or("127.0.0.1".to_string()), None)
+        } else if re_server_address.is_none() {
+            Self::search_for_ip(
                 re_server_address, 
-                Duration::from_secs(30)
-            );
-            services::confirm(&remote_address);
-            remote_address
+                Duration::from_secs(30),
+                &options,
+            )
         } else {
             warn!("start in localhost mode");
-            re_server_address.unwrap()
+            (re_server_address.unwrap(), None)
         };
 
+        // aes67 streams are never encrypted
+        let encrypted = match (&options.aes67, &announcement) {
+            (Some(_), _) => Some(false),
+            (None, announcement) => announcement.as_ref().map(|announcement| announcement.encrypted),
+        };
+        let encrypted = options.encryption.decrypt(encrypted)?;
 
-        let clock = create_clock(&clock_rtcp_server_address, clock_port.unwrap_or(8555))?;
+        // srtpdec is configured with the suite the server announces
+        let cipher_suite = match announcement.as_ref().filter(|announcement| announcement.encrypted && encrypted) {
+            Some(announcement) => announcement.srtp_cipher_suite()?,
+            None => options.cipher_suite,
+        };
+
+        let keyring = match (&options.key_provider, encrypted) {
+            (_, false) => None,
+            (None, true) => return Err(anyhow!("the stream is encrypted, but no key provider is set")),
+            (Some(_), true) if !cipher_suite.is_available() => {
+                return Err(anyhow!("srtp crypto suite {} of the stream is not supported by the installed srtpdec", cipher_suite));
+            },
+            (Some(provider), true) => Some(Arc::new(KeyRing::from_provider(provider, cipher_suite)?)),
+        };
+        // the server wraps the session keys for this key pair if it uses the key exchange
+        let key_exchange = keyring.as_ref().map(|_| KeyExchange::new()).transpose()?;
+        let public_key = key_exchange.as_ref().map(|key_exchange| key_exchange.public_key());
+
+        // only confirm a server which is not refused
+        if options.aes67.is_none() && server_address == "0.0.0.0" {
+            // the control listener is not started yet, the server learns its port with the next confirmation
+            services::confirm(&clock_rtcp_server_address, public_key, options.credential.as_ref(), None, options.dscp.control);
+        }
+
+
+        let clock = clock::create_client_clock(&options.clock, &clock_rtcp_server_address, options.dscp.control)?;
         let _ = clock.wait_for_sync(Some(5 * gst::ClockTime::SECOND));
+        info!("clock sync status: {:?}", SyncStatus::of_clock(&options.clock, &clock));
         info!("send rtcp data and NTP Clock to {} & recive rtp data to 0.0.0.0", clock_rtcp_server_address);
 
         let use_sync_on_buffer_mode = std::env::var("USE_BUFFER_MODE_SYNC").unwrap_or("1".to_string()) == "1";
@@ -136,44 +446,113 @@ impl PlaybackClient {
         pipeline.use_clock(Some(&clock));
         pipeline.set_latency(Some(gst::ClockTime::from_mseconds(LATENCY as u64)));
 
-        let (convert, source, rtpbin, rtpdepayload, rtp_src) = create_pipeline(
+        let (convert, source, rtpbin, rtp_src) = create_pipeline(
             &pipeline,
             rtp_port, 
             &clock_rtcp_server_address,
             latency,
             !use_sync_on_buffer_mode,
             audio_device.clone(),
+            &options,
+            keyring.as_ref(),
         )?;
 
 
+        let path_counters = if options.redundancy.is_some() {
+            ["rtp_eingang", "rtp_eingang2"].iter()
+                .filter_map(|name| count_path(&pipeline, name))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
         let pipeline_weak = pipeline.downgrade();
         let pipeline_2weak = pipeline.downgrade();
 
         let bus = pipeline.bus().unwrap();
-        let audio_in_src = convert.static_pad("src").unwrap();
+        clock::post_statistics(&clock, &bus);
+        let audio_in_src = pipeline.by_name("volume").unwrap().static_pad("src").unwrap();
         let weak_rtpbin = rtpbin.downgrade();
         let weak_pipeline_for_confirmation = pipeline.downgrade();
 
+        // the codec of the stream, aes67 streams are always L24
+        let audio_format = match &options.aes67 {
+            Some(aes67) => (aes67.payload_type, aes67.caps()),
+            None => {
+                let format = announced_format(announcement.as_ref());
+                (format.payload_type, format.caps())
+            },
+        };
+        let mut payload_formats = HashMap::from([audio_format]);
+        // the jitterbuffer drops packets of payload types without clock-rate
+        if options.fec {
+            let format = MediaFormat::new(FEC_PAYLOAD_TYPE, "ULPFEC", DEFAULT_AUDIO_RATE as u32, None);
+            payload_formats.insert(format.payload_type, format.caps());
+        }
+        if options.retransmission {
+            let format = MediaFormat::new(RTX_PAYLOAD_TYPE, "rtx", DEFAULT_AUDIO_RATE as u32, None)
+                .with_fmtp(&format!("apt={}", PAYLOAD_TYPE));
+            payload_formats.insert(format.payload_type, format.caps());
+        }
+
         let state = State { 
             rtpbin: rtpbin,
+            clock,
             source,
             audio_in_src,
             recv_rtp_src: None,
+            decoder: None,
             current_output_element: "alsasink".to_string(),
             current_output_device: audio_device.unwrap_or("".to_string()),
             sender_clock_address:  server_address.to_string(),
+            server_address: clock_rtcp_server_address.clone(),
+            rtt: None,
+            clock_offset: None,
+            recent_errors: std::collections::VecDeque::new(),
         };
 
 
         let playbackclient = PlaybackClient(Arc::new(PlaybackClientInner { 
             pipeline,
             convert,
-            rtpdepayload,
+            payload_formats: Mutex::new(payload_formats),
+            decoder_change: Mutex::new(()),
             audio_rate: audio_rate.unwrap_or(DEFAULT_AUDIO_RATE),
+            options,
+            path_counters,
+            control: Mutex::new(None),
+            keyring,
+            key_exchange,
+            encrypted,
             state: Arc::new(Mutex::new(state)),
             timeout_error_handling_is_active: AtomicBool::new(false),
         }));
 
+        // the keys of an encrypted stream are distributed over the control channel, also without remote control
+        if playbackclient.options.remote_control || playbackclient.keyring.is_some() {
+            let pbc = playbackclient.downgrade();
+            let remote_control = playbackclient.options.remote_control;
+            let port = playbackclient.options.control_port.unwrap_or(CONTROL_PORT);
+            let listener = ControlListener::new(port, playbackclient.options.dscp.control, move |from, command| {
+                let pbc = pbc.upgrade().ok_or_else(|| anyhow!("playback client is gone"))?;
+                // every client knows the shared secret, only the server may send commands
+                let server_address = pbc.state.lock().server_address.clone();
+                if !is_address_of(&server_address, from) {
+                    return Err(anyhow!("{} is not the server {}", from, server_address));
+                }
+                match command {
+                    ControlCommand::AddKey { .. } => pbc.execute(command),
+                    _ if remote_control => pbc.execute(command),
+                    _ => Err(anyhow!("remote control is disabled")),
+                }
+            })?;
+            *playbackclient.control.lock() = Some(listener);
+        }
+
+        let send_confirmations = playbackclient.options.aes67.is_none();
+        let credential = playbackclient.options.credential.clone();
+        let dscp = playbackclient.options.dscp.control;
+        let control_port = playbackclient.control_port();
         glib::timeout_add(Duration::from_millis(services::RECONFIRMATIONTIME_IN_MS), move || {
             let pipeline = match weak_pipeline_for_confirmation.upgrade() {
                 Some(pipeline) => {
@@ -181,13 +560,17 @@ impl PlaybackClient {
                 },
                 None => return Continue(true),
             };
+
+            if !send_confirmations {
+                return Continue(true);
+            }
             
             // only send confirmation if we not in localhost mode
             if let Some(rtcp) = pipeline.by_name("rtcp_senden") {
                 let hostaddress = rtcp.property::<String>("host");
                 if hostaddress != "127.0.0.1" && hostaddress != "0.0.0.0" {
                     debug!("resend confirmation to: {}", hostaddress);
-                    services::confirm(&hostaddress)
+                    services::confirm(&hostaddress, public_key, credential.as_ref(), control_port, dscp)
                 }
             }
 
@@ -203,6 +586,26 @@ impl PlaybackClient {
             };
  
             info!("player - current pipeline state: {:?}", pipeline.state(Some(gst::ClockTime::from_seconds(1))));
+            if let Some(clock) = pipeline.clock() {
+                info!("player - clock synced: {}", clock.is_synced());
+            }
+
+            Continue(true)
+        });
+
+        let weak_playbackclient = playbackclient.downgrade();
+        glib::timeout_add(Duration::from_millis(services::REPORT_INTERVAL_IN_MS), move || {
+            let pbc = upgrade_weak!(weak_playbackclient, Continue(true));
+            if !send_confirmations {
+                return Continue(true);
+            }
+
+            if let Some(rtcp) = pbc.pipeline.by_name("rtcp_senden") {
+                let hostaddress = rtcp.property::<String>("host");
+                if hostaddress != "0.0.0.0" {
+                    services::send_report(&hostaddress, &pbc.report(), pbc.options.dscp.control);
+                }
+            }
 
             Continue(true)
         });
@@ -213,32 +616,56 @@ impl PlaybackClient {
             let name = pad.name().to_string();
             
             let pbc = upgrade_weak!(weak_playbackclient);
-            let decoder = &pbc.rtpdepayload;
-            let decoder_sink = decoder.static_pad("sink").unwrap();
 
-            debug!("rtpbin pad_added: {} - {:?}", name, decoder);
+            debug!("rtpbin pad_added: {}", name);
     
             if name.contains("recv_rtp_src") {
-
-                let mut state_guard = pbc.state.lock();
-                
-                if let Some(recv_rtp_src) = state_guard.recv_rtp_src.as_ref() {
-                    info!("already initiate a recv_rtp pad {}. unlink it from rtpdepayload sink", recv_rtp_src.name());
-                    let _ = recv_rtp_src.unlink(&decoder_sink);
-                    info!("src_pad from rtpdepayload sink removed");
+                let caps = pad.current_caps().unwrap_or_else(|| pad.query_caps(None));
+                if let Err(e) = pbc.link_decoder(pad, &caps) {
+                    warn!("could not decode the stream of pad {}: {}", name, e);
                 }
 
-                state_guard.recv_rtp_src = Some(pad.clone());
-                drop(state_guard);
-    
-                info!("link newley created pad {} to rtpdepayload sink", pad.name());
-                debug!("pad: {:#?}", pad);
-                pad.link(&decoder_sink).expect("link of rtpbin pad to rtpdepayload sink should work");
-    
+                // the caps change after `clear-pt-map` if the server changed the codec
+                let weak_playbackclient = pbc.downgrade();
+                pad.add_probe(gst::PadProbeType::EVENT_DOWNSTREAM, move |pad, info| {
+                    if let Some(gst::PadProbeData::Event(event)) = &info.data {
+                        if let (gst::EventView::Caps(caps), Some(pbc)) = (event.view(), weak_playbackclient.upgrade()) {
+                            if let Err(e) = pbc.link_decoder(pad, &caps.caps_owned()) {
+                                warn!("could not decode the stream of pad {} with caps {}: {}", pad.name(), caps.caps(), e);
+                            }
+                        }
+                    }
+                    gst::PadProbeReturn::Ok
+                });
             }
 
         });
 
+        // a new stream or payload type may come with another codec, e.g. after a restart of the server
+        for signal in ["on-new-ssrc", "payload-type-change"] {
+            let weak_playbackclient = playbackclient.downgrade();
+            rtpbin.connect(signal, false, move |_| {
+                let weak_playbackclient = weak_playbackclient.clone();
+                // the probe of the server must not block the streaming thread
+                std::thread::spawn(move || {
+                    if let Some(pbc) = weak_playbackclient.upgrade() {
+                        pbc.refresh_payload_format();
+                    }
+                });
+                None
+            });
+        }
+
+        // caps of the payload types without caps on the udpsrc
+        let weak_playbackclient = playbackclient.downgrade();
+        rtpbin.connect("request-pt-map", false, move |values| {
+            let pbc = upgrade_weak!(weak_playbackclient, None);
+            let pt = values[2].get::<u32>().unwrap();
+            let caps = pbc.payload_formats.lock().get(&pt).cloned();
+            debug!("request pt map for payload type {}: {:?}", pt, caps);
+            caps.map(|caps| caps.to_value())
+        });
+
         let weak_playbackclient = playbackclient.downgrade();
         // Bus for error handling
         bus.add_watch(move |_, msg| {
@@ -267,6 +694,11 @@ impl PlaybackClient {
                         err.debug()
                     );
 
+                    if let Some(pbc) = weak_playbackclient.upgrade() {
+                        let name = err.src().map(|s| s.name().to_string()).unwrap_or_default();
+                        pbc.record_error(format!("{}: {}", name, err.error()));
+                    }
+
                     let src = match err.src().and_then(|s| s.clone().downcast::<gst::Element>().ok()) {
                         None => {
                             warn!("could not handle error cause no element found");
@@ -322,6 +754,13 @@ impl PlaybackClient {
                 MessageView::Warning(warning) => {
                     warn!("Warning: \"{}\"", warning.debug().unwrap());
                 }
+                MessageView::Element(e) if e.structure().map(|s| s.name() == "gst-netclock-statistics").unwrap_or(false) => {
+                    let pbc = upgrade_weak!(weak_playbackclient, glib::Continue(true));
+                    let statistics = e.structure().unwrap();
+                    let mut state = pbc.state.lock();
+                    state.rtt = statistics.get::<gst::ClockTime>("rtt-average").ok();
+                    state.clock_offset = statistics.get::<i64>("local-clock-offset").ok();
+                }
                 MessageView::Element(e) => {
                     if let Some(obj) = e.src() {
                         if obj.name() == "rtp_eingang" {
@@ -351,6 +790,53 @@ impl PlaybackClient {
         )
     }
 
+    /// List the sessions announced via SAP, waits `timeout` for announcements
+    pub fn list_sessions(timeout: Duration) -> Result<Vec<SapSession>, anyhow::Error> {
+        services::sap::discover_sessions(timeout)
+    }
+
+    /// Create a Playback Client for a session announced via SAP
+    /// 
+    /// AES67 sessions (L24 synced by PTP) are received in AES67 mode,
+    /// all other sessions are received from the announcing server
+    /// 
+    /// * `session` - session from [`PlaybackClient::list_sessions`]
+    /// * `latency` - latency of the stream
+    /// * `audio_device` - Optional audio device name, e.g. hw:0,0
+    /// * `options` - additional options, see [`PlaybackClientOptions`]
+    pub fn new_from_session(
+        session: &SapSession,
+        latency: Option<i32>,
+        audio_device: Option<String>,
+        mut options: PlaybackClientOptions,
+    ) -> Result<PlaybackClient, anyhow::Error> {
+        let description = &session.description;
+        info!("create playback client for session {} from {}", description.session_name, session.source);
+
+        if let Some(aes67) = Aes67Options::from_session_description(description) {
+            options.aes67 = Some(aes67);
+        }
+
+        let audio_rate = description.formats.first().map(|format| format.clock_rate as i32);
+        // the codec of other sessions is only known from the sdp
+        let audio_format = description.formats.first().filter(|_| options.aes67.is_none()).cloned();
+
+        let playbackclient = Self::new_with_options(
+            &session.source.to_string(),
+            description.port as i32,
+            None,
+            audio_rate,
+            latency,
+            audio_device,
+            options,
+        )?;
+
+        if let Some(format) = audio_format {
+            playbackclient.set_payload_format(&format);
+        }
+        Ok(playbackclient)
+    }
+
     /// Start the player
     ///
     /// befor start with set_state(gst::State::Playing) the start_time is set to gst::ClockTime::NONE
@@ -370,6 +856,182 @@ impl PlaybackClient {
         let _ = self.pipeline.set_state(gst::State::Null);
     }
 
+    /// Execute a command of the remote control channel
+    pub fn execute(&self, command: ControlCommand) -> Result<(), anyhow::Error> {
+        match command {
+            ControlCommand::SetVolume(volume) => self.set_volume(volume),
+            ControlCommand::Mute(mute) => self.set_mute(mute),
+            ControlCommand::ChangeOutput { element, device } => self.change_output(&element, device.as_deref()),
+            ControlCommand::SetLatency(latency) => self.set_latency(latency),
+            ControlCommand::Restart => {
+                self.stop();
+                self.pipeline.set_base_time(gst::ClockTime::ZERO);
+                self.pipeline.set_state(gst::State::Playing)?;
+                Ok(())
+            },
+            ControlCommand::Identify => self.identify(),
+            ControlCommand::AddKey { mki, wrapped, server_key } => self.add_key(&mki, &wrapped, server_key.as_deref()),
+        }
+    }
+
+    /// port of the control listener, `None` if the client does not listen for commands
+    fn control_port(&self) -> Option<u16> {
+        self.control.lock().as_ref().map(|listener| listener.local_addr().port())
+    }
+
+    /// Add a key of the SRTP key rotation or the session key, distributed by the server
+    /// 
+    /// the client encrypts its RTCP packets with the newest key,
+    /// the stream of the server is decrypted with the key of its MKI
+    fn add_key(&self, mki: &[u8], wrapped: &[u8], server_key: Option<&[u8]>) -> Result<(), anyhow::Error> {
+        let keyring = self.keyring.as_ref().ok_or_else(|| anyhow!("srtp is not configured"))?;
+        let wrapping_key = match (server_key, &self.key_exchange) {
+            (Some(server_key), Some(key_exchange)) => key_exchange.wrapping_key(server_key)?,
+            (Some(_), None) => return Err(anyhow!("no key exchange")),
+            (None, _) => shared_wrapping_key()?,
+        };
+        let key = unwrap_key(&wrapping_key, mki, wrapped)?;
+
+        // mkis are big endian counters, so the bytes compare like the numbers
+        let newest = keyring.keys().last().map(|newest| newest.mki.as_slice() < mki).unwrap_or(true);
+        keyring.insert(key)?;
+        if newest {
+            keyring.activate(mki)?;
+        }
+        Ok(())
+    }
+
+    /// Set the volume of the output, 1.0 is the original volume
+    pub fn set_volume(&self, volume: f64) -> Result<(), anyhow::Error> {
+        if !(0.0..=10.0).contains(&volume) {
+            return Err(anyhow!("volume {} out of range 0.0 - 10.0", volume));
+        }
+        let element = self.pipeline.by_name("volume").ok_or_else(|| anyhow!("no volume element"))?;
+        element.set_property("volume", volume);
+        Ok(())
+    }
+
+    /// Mute or unmute the output
+    pub fn set_mute(&self, mute: bool) -> Result<(), anyhow::Error> {
+        let element = self.pipeline.by_name("volume").ok_or_else(|| anyhow!("no volume element"))?;
+        element.set_property("mute", mute);
+        Ok(())
+    }
+
+    /// Set the latency of the jitterbuffer in ms
+    pub fn set_latency(&self, latency: u32) -> Result<(), anyhow::Error> {
+        let rtpbin = self.pipeline.by_name("rtpbin").ok_or_else(|| anyhow!("no rtpbin"))?;
+        rtpbin.set_property("latency", latency);
+        Ok(())
+    }
+
+    /// Play a short tone on the current output to find the client
+    pub fn identify(&self) -> Result<(), anyhow::Error> {
+        let (element, device) = {
+            let state = self.state.lock();
+            (state.current_output_element.clone(), state.current_output_device.clone())
+        };
+
+        let pipeline = gst::Pipeline::new(Some("identify"));
+        let src = make_element("audiotestsrc", None)?;
+        src.set_property("freq", 880.0f64);
+        // ~1 second with the default 1024 samples per buffer
+        src.set_property("num-buffers", 43i32);
+        let convert = make_element("audioconvert", None)?;
+        let sink = gst::ElementFactory::make_with_name(&element, None)?;
+        if !device.is_empty() && sink.has_property("device", None) {
+            sink.set_property("device", &device);
+        }
+        pipeline.add_many(&[&src, &convert, &sink])?;
+        gst::Element::link_many(&[&src, &convert, &sink])?;
+        pipeline.set_state(gst::State::Playing)?;
+
+        std::thread::spawn(move || {
+            if let Some(bus) = pipeline.bus() {
+                let _ = bus.timed_pop_filtered(gst::ClockTime::from_seconds(5), &[gst::MessageType::Eos, gst::MessageType::Error]);
+            }
+            let _ = pipeline.set_state(gst::State::Null);
+        });
+        Ok(())
+    }
+
+    fn record_error(&self, error: String) {
+        let mut state = self.state.lock();
+        if state.recent_errors.len() >= MAX_RECENT_ERRORS {
+            state.recent_errors.pop_front();
+        }
+        // keep the report in one datagram
+        state.recent_errors.push_back(error.chars().take(120).collect());
+    }
+
+    /// Status of the client, which is send periodically to the server
+    pub fn report(&self) -> ClientReport {
+        let jitterbuffer = self.pipeline.by_name("rtpbin")
+            .and_then(|rtpbin| rtpbin.downcast::<gst::Bin>().ok())
+            .and_then(|rtpbin| {
+                rtpbin.iterate_recurse().into_iter().flatten().find(|element| {
+                    element.factory().map(|f| f.name() == "rtpjitterbuffer").unwrap_or(false)
+                })
+            });
+        let stats = jitterbuffer.as_ref().map(|jitterbuffer| jitterbuffer.property::<gst::Structure>("stats"));
+        let volume = self.pipeline.by_name("volume");
+
+        let state = self.state.lock();
+        let clock_offset = state.clock_offset.or_else(|| {
+            // without statistics the offset of the last calibration
+            let (internal, external, _, _) = state.clock.calibration();
+            Some(external.nseconds() as i64 - internal.nseconds() as i64)
+        });
+
+        ClientReport {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            state: format!("{:?}", self.pipeline.current_state()),
+            jitterbuffer_percent: jitterbuffer.as_ref().map(|jitterbuffer| jitterbuffer.property::<i32>("percent")),
+            jitter_us: stats.as_ref().and_then(|stats| stats.get::<u64>("avg-jitter").ok()).map(|jitter| jitter / 1000),
+            packets_lost: stats.as_ref().and_then(|stats| stats.get::<u64>("num-lost").ok()).unwrap_or(0),
+            clock_offset_ns: clock_offset,
+            rtt_us: state.rtt.map(|rtt| rtt.useconds()),
+            output_element: state.current_output_element.clone(),
+            output_device: state.current_output_device.clone(),
+            volume: volume.as_ref().map(|volume| volume.property::<f64>("volume")).unwrap_or(1.0),
+            muted: volume.as_ref().map(|volume| volume.property::<bool>("mute")).unwrap_or(false),
+            errors: state.recent_errors.iter().cloned().collect(),
+        }
+    }
+
+    /// Synchronization status of the clock to the broadcaster
+    pub fn sync_status(&self) -> SyncStatus {
+        let state = self.state.lock();
+        SyncStatus::of_clock(&self.options.clock, &state.clock)
+    }
+
+    /// Counters of the retransmission receiver, `None` if retransmission is not enabled
+    /// or no stream was received yet
+    pub fn retransmission_stats(&self) -> Option<RetransmissionStats> {
+        let rtxreceive = self.pipeline.by_name("rtxreceive0")?;
+
+        Some(RetransmissionStats {
+            requests: rtxreceive.property::<u32>("num-rtx-requests"),
+            packets: rtxreceive.property::<u32>("num-rtx-packets"),
+        })
+    }
+
+    /// Counters of the FEC decoder, `None` if FEC is not enabled
+    /// or no stream was received yet
+    pub fn fec_stats(&self) -> Option<FecStats> {
+        let fecdec = self.pipeline.by_name("fecdec0")?;
+
+        Some(FecStats {
+            recovered: fecdec.property::<u32>("recovered"),
+            unrecovered: fecdec.property::<u32>("unrecovered"),
+        })
+    }
+
+    /// Counters of both receive paths, empty if redundancy is not enabled
+    pub fn path_stats(&self) -> Vec<PathStats> {
+        self.path_counters.iter().map(|counter| counter.lock().stats).collect()
+    }
+
     // currently does not work.. hang async 
     fn try_reconnect(&self) {
         if self.timeout_error_handling_is_active.load(Ordering::Relaxed) {
@@ -394,7 +1056,8 @@ impl PlaybackClient {
                 rets
             };
 
-            let sender_clock_address = if current_sender_clock_address == "0.0.0.0" {
+            // with known servers the next answering one is probed
+            let sender_clock_address = if current_sender_clock_address == "0.0.0.0" || !pbc.options.static_servers.is_empty() {
                 None
             } else {
                 Some(current_sender_clock_address)
@@ -405,6 +1068,101 @@ impl PlaybackClient {
 
     }
 
+    /// Link the pad of a new stream of the rtpbin to a decoder for the encoding of the stream
+    /// 
+    /// the decoder of the previous stream is reused for the same encoding, otherwise it is replaced.
+    /// called from the streaming threads, the state is only locked to read and update the decoder
+    fn link_decoder(&self, pad: &gst::Pad, caps: &gst::Caps) -> Result<(), anyhow::Error> {
+        let encoding_name = caps.structure(0)
+            .and_then(|structure| structure.get::<String>("encoding-name").ok())
+            .ok_or_else(|| anyhow!("no encoding name in caps {}", caps))?;
+
+        let _decoder_change = self.decoder_change.lock();
+        let (current, previous_pad) = {
+            let state = self.state.lock();
+            (state.decoder.clone(), state.recv_rtp_src.clone())
+        };
+
+        let same_encoding = matches!(&current, Some((encoding, _)) if *encoding == encoding_name);
+        if same_encoding && previous_pad.as_ref() == Some(pad) {
+            return Ok(());
+        }
+
+        // create the new decoder first, so the current stream keeps playing if it fails
+        let decoder = match &current {
+            Some((_, decoder)) if same_encoding => decoder.clone(),
+            _ => create_decoder(&encoding_name)?,
+        };
+
+        if let Some(previous_pad) = previous_pad {
+            info!("already initiate a recv_rtp pad {}. unlink it from the decoder", previous_pad.name());
+            if let Some(peer) = previous_pad.peer() {
+                let _ = previous_pad.unlink(&peer);
+            }
+        }
+
+        if !same_encoding {
+            if let Some((encoding, old_decoder)) = current {
+                info!("replace the decoder of the {} stream", encoding);
+                old_decoder.unlink(&self.convert);
+                let _ = old_decoder.set_state(gst::State::Null);
+                self.pipeline.remove(&old_decoder)?;
+            }
+
+            info!("decode the {} stream with {}", encoding_name, decoder.name());
+            self.pipeline.add(&decoder)?;
+            decoder.link(&self.convert)?;
+            decoder.sync_state_with_parent()?;
+        }
+
+        let decoder_sink = decoder.static_pad("sink").ok_or_else(|| anyhow!("decoder has no sink pad"))?;
+        {
+            let mut state = self.state.lock();
+            state.decoder = Some((encoding_name, decoder));
+            state.recv_rtp_src = Some(pad.clone());
+        }
+
+        info!("link newley created pad {} to decoder sink", pad.name());
+        pad.link(&decoder_sink)?;
+        Ok(())
+    }
+
+    /// Fetch the announcement of the current server and follow a change of its codec
+    ///
+    /// called for a new stream or payload type, also for servers with an explicit address
+    fn refresh_payload_format(&self) {
+        if self.options.aes67.is_some() {
+            return;
+        }
+
+        let server_address = self.state.lock().server_address.clone();
+        match services::probe_server(&server_address, Duration::from_secs(2), self.options.dscp.control) {
+            Some((_, announcement)) => self.set_payload_format(&announced_format(Some(&announcement))),
+            None => debug!("server {} did not answer the probe, keep the payload format", server_address),
+        }
+    }
+
+    /// Set the payload format of the stream, e.g. if the server changed the codec
+    /// 
+    /// the rtpbin requests the caps of the payload types again, the next stream of the server
+    /// gets a decoder for the new encoding. the format is set from the announcement of the server
+    /// in [`PlaybackClient::change_server`] and for every new stream of the server
+    pub fn set_payload_format(&self, format: &MediaFormat) {
+        let caps = format.caps();
+        let mut payload_formats = self.payload_formats.lock();
+        if payload_formats.get(&format.payload_type) == Some(&caps) {
+            return;
+        }
+
+        info!("payload type {} is {} now", format.payload_type, format.encoding_name);
+        payload_formats.insert(format.payload_type, caps);
+        drop(payload_formats);
+
+        if let Some(rtpbin) = self.pipeline.by_name("rtpbin") {
+            rtpbin.emit_by_name::<()>("clear-pt-map", &[]);
+        }
+    }
+
     /// Change Server and clock address
     /// 
     /// # Arguments
@@ -412,35 +1170,76 @@ impl PlaybackClient {
     /// * `sender_clock_address` - IP Address / Hostname of the clock provider, should not be a multicast address
     ///                            if None we will try to find a broadcast message
     pub fn change_server(&self, sender_clock_address: Option<String>) -> Result<(), anyhow::Error> {
-        let l_sender_clock_address  = 
+        let (l_sender_clock_address, announcement) = 
             Self::search_for_ip(
                 sender_clock_address.clone(), 
-                Duration::from_secs(30)
+                Duration::from_secs(30),
+                &self.options,
             );
+
+        // servers answer probes with their announcement, e.g. with the codec after a restart
+        let announcement = match (announcement, &sender_clock_address, &self.options.aes67) {
+            (None, Some(address), None) if address != "127.0.0.1" => {
+                services::probe_server(address, Duration::from_secs(2), self.options.dscp.control).map(|(_, announcement)| announcement)
+            },
+            (announcement, _, _) => announcement,
+        };
+
+        // the pipeline decrypts the stream or not, it is not rebuild for the new server
+        if let Some(announcement) = announcement.as_ref().filter(|announcement| announcement.encrypted != self.encrypted) {
+            return Err(anyhow!(
+                "server {} ({}) has {} stream, refused",
+                l_sender_clock_address, announcement.server_id, if announcement.encrypted { "an encrypted" } else { "an unencrypted" }
+            ));
+        }
+        if let (Some(announcement), Some(keyring)) = (&announcement, &self.keyring) {
+            if announcement.cipher_suite != keyring.suite().name() {
+                return Err(anyhow!(
+                    "server {} ({}) uses the srtp crypto suite {}, not {}, refused",
+                    l_sender_clock_address, announcement.server_id, announcement.cipher_suite, keyring.suite()
+                ));
+            }
+        }
+
+        // the streams of the server are decoded with its codec
+        if let (Some(announcement), None) = (&announcement, &self.options.aes67) {
+            self.set_payload_format(&announced_format(Some(announcement)));
+        }
         
         let mut state = self.state.lock();
+        state.server_address = l_sender_clock_address.clone();
         if state.sender_clock_address == l_sender_clock_address {
             info!("player - change_server - no change in address clock_rtcp_sender:{}", l_sender_clock_address);
             return Ok(())
         }
+        // the streaming threads lock the state to change the decoder, stopping the pipeline waits for them
+        drop(state);
 
         // always send a confirm message
         //if &l_sender_clock_address != "127.0.0.1" {
             info!("send confirm message to {}", l_sender_clock_address);
-            services::confirm(&l_sender_clock_address);
+            services::confirm(&l_sender_clock_address, self.key_exchange.as_ref().map(|key_exchange| key_exchange.public_key()), self.options.credential.as_ref(), self.control_port(), self.options.dscp.control);
         //}
 
         if let Err(e) = self.pipeline.set_state(gst::State::Null) {
             warn!("error on call stop pipeline inside change_server error : {}", e)
         }
+        let mut state = self.state.lock();
         // lock for a broadcast message because address is 0.0.0.0
         if state.sender_clock_address != l_sender_clock_address {
-            if sender_clock_address.is_some() {
+            if sender_clock_address.is_some() || !self.options.static_servers.is_empty() {
                 state.sender_clock_address = l_sender_clock_address.to_string();
             }
             warn!("change clock and rtcpsender set {}", l_sender_clock_address);
-            let clock = create_clock(&l_sender_clock_address, 8555)?;
-            self.pipeline.use_clock(Some(&clock));
+            // only the net time clock depends on the server
+            if let ClockSource::NetTime { .. } = self.options.clock {
+                let clock = clock::create_client_clock(&self.options.clock, &l_sender_clock_address, self.options.dscp.control)?;
+                if let Some(bus) = self.pipeline.bus() {
+                    clock::post_statistics(&clock, &bus);
+                }
+                self.pipeline.use_clock(Some(&clock));
+                state.clock = clock;
+            }
             change_ip(&self.pipeline, "rtcp_senden", &l_sender_clock_address, true)?;
         }
         
@@ -464,24 +1263,68 @@ impl PlaybackClient {
     /// * `rtp_receiver_address` - current IP Address / Hostname of the RTP Stream provider, can also be a multicast address
     /// * `sender_clock_address` - current IP Address / Hostname of the clock provider, should not be a multicast address
     /// * `timeout` - timeout for the broadcast message
+    /// * `options` - discovery method and interfaces where we listen for the broadcast message
     /// 
     /// # Return
     /// * (sender_clock_address, rtp_receiver_address)
-    fn search_for_ip(sender_clock_address: Option<String>, timeout: Duration) -> String {
+    fn search_for_ip(sender_clock_address: Option<String>, timeout: Duration, options: &PlaybackClientOptions) -> (String, Option<Announcement>) {
         if sender_clock_address.is_some() {
             warn!("search_for_ip: we have a sender_clock_address: {:?}", sender_clock_address);
-            sender_clock_address.unwrap()
+            (sender_clock_address.unwrap(), None)
         } else {
-            let search_result = services::wait_for_broadcast(timeout).map_or(
-                "127.0.0.1".into(), 
-                |r| {
+            // servers with streams refused by the encryption policy are ignored
+            let policy = options.encryption;
+            let accept = move |announcement: &Announcement| policy.accepts(announcement.encrypted);
+
+            if !options.static_servers.is_empty() {
+                if let Some((address, announcement)) = services::probe_servers(&options.static_servers, Duration::from_secs(2), options.dscp.control, accept) {
+                    info!("use known server {} ({})", address, announcement.server_id);
+                    return (address.to_string(), Some(announcement));
+                }
+                warn!("no known server answered, search via discovery");
+            }
+
+            let found = match &options.server_selection {
+                Some(selection) => Self::select_server(timeout, selection, options),
+                None => discovery::discover(timeout, options.discovery, &options.interfaces, accept),
+            };
+            found.map_or(
+                ("127.0.0.1".into(), None), 
+                |(address, announcement)| {
                     trace!("we got a broadcast message");
-                    r.0.to_string()
+                    (address.to_string(), Some(announcement))
                 }
-            );
+            )
+        }
+    }
 
-            sender_clock_address.unwrap_or(search_result)
+    /// Collect the servers for the window of the selection and choose one
+    /// 
+    /// repeats until a server matches the selection or the timeout is reached.
+    /// servers with streams refused by the encryption policy are ignored
+    fn select_server(timeout: Duration, selection: &ServerSelection, options: &PlaybackClientOptions) -> Option<(std::net::IpAddr, Announcement)> {
+        let start_instant = std::time::Instant::now();
+        while start_instant.elapsed() < timeout {
+            let window = selection.window.max(discovery::MIN_SELECTION_WINDOW).min(timeout.saturating_sub(start_instant.elapsed()));
+            let mut servers = discovery::collect_servers(window, options.discovery, &options.interfaces);
+            debug!("found {} servers: {:?}", servers.len(), servers);
+            servers.retain(|server| options.encryption.accepts(server.announcement.encrypted));
+
+            if let Some(server) = selection.select(&servers) {
+                info!("select server {} ({}) at {}", server.announcement.name, server.announcement.server_id, server.address);
+                return Some((server.address, server.announcement.clone()));
+            }
         }
+        None
+    }
+
+    /// Discover all servers within the window
+    /// 
+    /// # Arguments
+    /// * `window` - how long to collect the announcements, the servers announce every 5 seconds
+    /// * `method` - broadcast, mDNS or both
+    pub fn discover_servers(window: Duration, method: DiscoveryMethod) -> Vec<discovery::DiscoveredServer> {
+        discovery::collect_servers(window, method, &services::InterfaceFilter::default())
     }
 
     ///
@@ -551,6 +1394,7 @@ impl PlaybackClient {
 /// * `latency` - Latency in ms
 /// * `buffe_mode_as_slave` - If true, the buffer-mode on rtpbin / jitterbuffer is slave. else its synced
 /// * `audio_device` - Optional audio device name, e.g. hw:0,0
+/// * `options` - additional options of the client
 /// 
 fn create_pipeline(
     pipeline: &gst::Pipeline,
@@ -559,10 +1403,15 @@ fn create_pipeline(
     latency: Option<i32>,
     buffe_mode_as_slave: bool,
     audio_device: Option<String>,
-) ->  Result<(gst::Element, gst::Element, gst::Element, gst::Element, gst::Element), anyhow::Error> {
-
-    //let caps = gst::Caps::from_str("application/x-rtp,channels=(int)2,format=(string)S16LE,media=(string)audio,payload=(int)96,clock-rate=(int)44100,encoding-name=(string)L24")?;
-    let caps = gst::Caps::from_str("application/x-rtp,channels=(int)2,format=(string)S16LE,media=(string)audio,payload=(int)96,clock-rate=(int)48000,encoding-name=(string)OPUS")?;
+    options: &PlaybackClientOptions,
+    encryption_keyring: Option<&Arc<KeyRing>>,
+) ->  Result<(gst::Element, gst::Element, gst::Element, gst::Element), anyhow::Error> {
+
+    // the caps of the payload types are requested by the rtpbin (`request-pt-map`), so the codec may change
+    let caps = match &options.aes67 {
+        Some(aes67) => aes67.caps(),
+        None => gst::Caps::from_str("application/x-rtp,media=(string)audio")?,
+    };
     let rtcp_caps = gst::Caps::from_str("application/x-rtcp")?;
 
     warn!("create playback pipeline with rtp port: {}, rtcp sender clock address: {}, latency: {:?}, use slave in buffer-mode: {}, audio_device: {:?}", rtp_port, rtcp_sender_clock_address, latency, buffe_mode_as_slave, audio_device);
@@ -575,6 +1424,32 @@ fn create_pipeline(
     //rtp_src.set_property("address", &rtp_and_rtcp_receiver_address);
     // immer der eigene host, da der rtp stream über den eigenen host kommt
     rtp_src.set_property("address", &"0.0.0.0");
+    if let Some(aes67) = &options.aes67 {
+        // aes67 streams are received from the multicast group
+        rtp_src.set_property("address", &aes67.multicast_group);
+        rtp_src.set_property("auto-multicast", true);
+    }
+    if let Some(iface) = options.redundancy.as_ref().and_then(|redundancy| redundancy.primary_iface.as_ref()) {
+        rtp_src.set_property("multicast-iface", iface);
+    }
+
+    let secondary_rtp_src = match &options.redundancy {
+        Some(redundancy) => {
+            let rtp_src = make_element("udpsrc", Some("rtp_eingang2"))?;
+            rtp_src.set_property("caps", &caps);
+            rtp_src.set_property("port", redundancy.secondary_port as i32);
+            // the server sends the second path to the multicast group only
+            let group = redundancy.secondary_multicast_group.as_ref()
+                .ok_or_else(|| anyhow!("redundancy requires the multicast group of the second path"))?;
+            rtp_src.set_property("address", group);
+            rtp_src.set_property("auto-multicast", true);
+            if let Some(iface) = &redundancy.secondary_iface {
+                rtp_src.set_property("multicast-iface", iface);
+            }
+            Some(rtp_src)
+        },
+        None => None,
+    };
 
     let rtcp_src = make_element("udpsrc", Some("rtcp_eingang"))?;
     rtcp_src.set_property("caps",&rtcp_caps);
@@ -587,6 +1462,7 @@ fn create_pipeline(
     rtcp_sink.set_property("host", &rtcp_sender_clock_address);
     rtcp_sink.set_property("async", false); 
     rtcp_sink.set_property("sync", false);
+    qos::mark_object(&rtcp_sink, options.dscp.control);
 
     let rtpbin = make_element("rtpbin", Some("rtpbin"))?;
 
@@ -613,6 +1489,64 @@ fn create_pipeline(
     }
     rtpbin.set_property("ntp-sync", true);
 
+    // the decoders and encoders are requested with the pads
+    if let Some(keyring) = encryption_keyring {
+        debug!("decrypt the stream with srtp");
+        encryption::client_encryption(&rtpbin, keyring)?;
+    }
+
+    if options.aes67.is_some() {
+        // the sender signals the ptp media clock (RFC 7273) in the sdp
+        rtpbin.set_property("rfc7273-sync", true);
+    }
+
+    if options.retransmission {
+        // NACKs are only send with the AVPF profile
+        rtpbin.set_property_from_str("rtp-profile", "avpf");
+        rtpbin.set_property("do-retransmission", true);
+
+        rtpbin.connect("request-aux-receiver", false, |data| {
+            let sid = data[1].get::<u32>().unwrap();
+            debug!("create retransmission receiver for session {}", sid);
+
+            match make_rtx_bin("rtprtxreceive", "rtxreceive", sid) {
+                Ok(bin) => Some(bin.upcast::<gst::Element>().to_value()),
+                Err(e) => {
+                    warn!("could not create retransmission receiver: {:?}", e);
+                    None
+                }
+            }
+        });
+    }
+
+    if options.fec {
+        // the fec decoder needs the last packets from the storage to recover lost ones
+        rtpbin.connect("new-storage", false, |data| {
+            let storage = data[1].get::<gst::Element>().unwrap();
+            storage.set_property("size-time", gst::ClockTime::from_mseconds(250).nseconds());
+            None
+        });
+
+        rtpbin.connect("request-fec-decoder", false, |data| {
+            let rtpbin = data[0].get::<gst::Element>().unwrap();
+            let sid = data[1].get::<u32>().unwrap();
+            debug!("create fec decoder for session {}", sid);
+
+            let fecdec = match make_element("rtpulpfecdec", Some(&format!("fecdec{}", sid))) {
+                Ok(fecdec) => fecdec,
+                Err(e) => {
+                    warn!("could not create fec decoder: {:?}", e);
+                    return None
+                }
+            };
+            let storage = rtpbin.emit_by_name::<glib::Object>("get-internal-storage", &[&sid]);
+            fecdec.set_property("storage", &storage);
+            fecdec.set_property("pt", FEC_PAYLOAD_TYPE);
+
+            Some(fecdec.to_value())
+        });
+    }
+
     // put all in the pipeline
     pipeline.add(&rtpbin)?;
 
@@ -620,13 +1554,26 @@ fn create_pipeline(
     pipeline.add(&rtcp_src)?;
     pipeline.add(&rtcp_sink)?;
 
-    rtp_src.link_pads(Some("src"), &rtpbin, Some("recv_rtp_sink_%u"))?;
+    if let Some(secondary_rtp_src) = &secondary_rtp_src {
+        // both paths are merged into one session. the duplicates are dropped before the rtpbin,
+        // and without the sender addresses the rtpsession does not take the second path for a ssrc collision
+        rtp_src.set_property("retrieve-sender-address", false);
+        secondary_rtp_src.set_property("retrieve-sender-address", false);
+        let funnel = make_element("funnel", Some("rtp_merge"))?;
+        pipeline.add(secondary_rtp_src)?;
+        pipeline.add(&funnel)?;
+        rtp_src.link(&funnel)?;
+        secondary_rtp_src.link(&funnel)?;
+        deduplicate(&funnel)?;
+        funnel.link_pads(Some("src"), &rtpbin, Some("recv_rtp_sink_%u"))?;
+    } else {
+        rtp_src.link_pads(Some("src"), &rtpbin, Some("recv_rtp_sink_%u"))?;
+    }
     rtcp_src.link_pads(Some("src"), &rtpbin, Some("recv_rtcp_sink_%u"))?;
     rtpbin.link_pads(Some("send_rtcp_src_%u"), &rtcp_sink, Some("sink"))?;
     
 
-    let rtpdepayload = make_element("rtpopusdepay", Some("rtpopusdepay"))?;
-    let dec = make_element("opusdec", Some("opusdec"))?;
+    // the decoder is linked to convert with the first stream, see `PlaybackClient::link_decoder`
     let convert = make_element("audioconvert", Some("convert"))?;
 
     let sink = if let Some(device) = audio_device {
@@ -637,29 +1584,58 @@ fn create_pipeline(
         make_element("autoaudiosink", Some("sink"))?
     };
 
-    pipeline.add(&rtpdepayload)?;
-    pipeline.add(&dec)?;
+    // volume and mute of the remote control
+    let volume = make_element("volume", Some("volume"))?;
+
     pipeline.add(&convert)?;
+    pipeline.add(&volume)?;
     pipeline.add(&sink)?;
 
     sink.set_property("sync", true);
 
-    gst::Element::link_many(&[&rtpdepayload, &dec, &convert, &sink])?;
+    gst::Element::link_many(&[&convert, &volume, &sink])?;
+
+    Ok((convert, sink, rtpbin, rtp_src))
+}
 
-    Ok((convert, sink, rtpbin, rtpdepayload, rtp_src))
+/// the host, an ip address or a hostname, has the address
+fn is_address_of(host: &str, address: IpAddr) -> bool {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip == address,
+        Err(_) => (host, 0).to_socket_addrs()
+            .map(|mut addresses| addresses.any(|resolved| resolved.ip() == address))
+            .unwrap_or(false),
+    }
 }
 
+/// payload format of the server, servers without announcement send OPUS
+fn announced_format(announcement: Option<&Announcement>) -> MediaFormat {
+    let codec = announcement.map(|announcement| announcement.codec.as_str()).unwrap_or("OPUS");
+    MediaFormat::new(PAYLOAD_TYPE, codec, DEFAULT_AUDIO_RATE as u32, Some(2))
+}
 
-/// creates a net clock client
-/// 
-/// # Arguments
-/// * `address` - the ip address of the clock server
-/// * `port` - the port of the clock server
-/// 
-fn create_clock(address: &str, port: i32) -> Result<gst_net::NetClientClock, anyhow::Error> {
-    let clock = gst_net::NetClientClock::new(None, address, port, gst::ClockTime::ZERO);
+/// depayloader and decoder for the encoding of a RTP stream, as bin with a sink and a src pad
+fn create_decoder(encoding_name: &str) -> Result<gst::Element, anyhow::Error> {
+    let elements = match encoding_name {
+        "OPUS" | "X-GST-OPUS-DRAFT-SPITTLER" => vec![make_element("rtpopusdepay", None)?, make_element("opusdec", None)?],
+        "L24" => vec![make_element("rtpL24depay", None)?],
+        "L16" => vec![make_element("rtpL16depay", None)?],
+        "PCMU" => vec![make_element("rtppcmudepay", None)?, make_element("mulawdec", None)?],
+        "PCMA" => vec![make_element("rtppcmadepay", None)?, make_element("alawdec", None)?],
+        encoding_name => return Err(anyhow!("no decoder for the encoding {}", encoding_name)),
+    };
+    let elements: Vec<&gst::Element> = elements.iter().collect();
+
+    let bin = gst::Bin::new(Some(&format!("decoder_{}", encoding_name.to_lowercase())));
+    bin.add_many(&elements)?;
+    gst::Element::link_many(&elements)?;
+
+    let sink = elements.first().and_then(|element| element.static_pad("sink")).ok_or_else(|| anyhow!("depayloader has no sink pad"))?;
+    let src = elements.last().and_then(|element| element.static_pad("src")).ok_or_else(|| anyhow!("decoder has no src pad"))?;
+    bin.add_pad(&gst::GhostPad::with_target(Some("sink"), &sink)?)?;
+    bin.add_pad(&gst::GhostPad::with_target(Some("src"), &src)?)?;
 
-    Ok(clock)
+    Ok(bin.upcast())
 }
 
 
@@ -684,4 +1660,92 @@ fn change_ip(pipeline: &gst::Pipeline, element: &str, address: &str, sink: bool)
         }
     };
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the forwarded packets
+    fn deduplicate(packets: &[(u32, u16)]) -> Vec<u16> {
+        let mut deduplicator = Deduplicator::default();
+        packets.iter().filter(|(ssrc, seq)| deduplicator.first(*ssrc, *seq)).map(|(_, seq)| *seq).collect()
+    }
+
+    fn count(packets: &[u16]) -> PathStats {
+        let mut counter = PathCounter::default();
+        for seq in packets {
+            counter.count(1, *seq);
+        }
+        counter.stats
+    }
+
+    #[test]
+    fn duplicates_are_dropped() {
+        // the second path is two packets behind, each path lost one packet
+        let first = [0, 1, 2, 4, 5, 6, 7, 8, 9];
+        let second = [0, 1, 2, 3, 4, 5, 6, 8, 9];
+        let mut packets = Vec::new();
+        for i in 0..first.len() + 2 {
+            if let Some(seq) = first.get(i) {
+                packets.push((1, *seq));
+            }
+            if let Some(seq) = i.checked_sub(2).and_then(|i| second.get(i)) {
+                packets.push((1, *seq));
+            }
+        }
+        assert_eq!(deduplicate(&packets), vec![0, 1, 2, 4, 5, 6, 3, 7, 8, 9]);
+    }
+
+    #[test]
+    fn deduplication_wraps_around() {
+        let packets = [65534, 65535, 0, 65535, 1, 0, 2].map(|seq| (1, seq));
+        assert_eq!(deduplicate(&packets), vec![65534, 65535, 0, 1, 2]);
+    }
+
+    #[test]
+    fn deduplication_with_large_skew() {
+        // the second path is 500 packets behind
+        let mut packets = Vec::new();
+        for seq in 0..2000u16 {
+            packets.push((1, seq));
+            if seq >= 500 {
+                packets.push((1, seq - 500));
+            }
+        }
+        assert_eq!(deduplicate(&packets), (0..2000).collect::<Vec<u16>>());
+
+        // packets older than the window are dropped
+        assert_eq!(deduplicate(&[(1, 5000), (1, 5000 - DEDUPLICATION_WINDOW), (1, 5001)]), vec![5000, 5001]);
+    }
+
+    #[test]
+    fn deduplication_restarts_with_a_new_ssrc() {
+        let packets = [(1, 10), (1, 11), (2, 10), (2, 11), (2, 11), (1, 12)];
+        assert_eq!(deduplicate(&packets), vec![10, 11, 10, 11, 12]);
+    }
+
+    #[test]
+    fn path_counter() {
+        assert_eq!(count(&[1, 2, 3]), PathStats { received: 3, lost: 0 });
+        assert_eq!(count(&[1, 2, 5, 6]), PathStats { received: 4, lost: 2 });
+        assert_eq!(count(&[1, 2, 2, 3]), PathStats { received: 4, lost: 0 });
+        assert_eq!(count(&[65534, 65535, 0, 2]), PathStats { received: 4, lost: 1 });
+    }
+
+    #[test]
+    fn reordered_packets_are_not_lost() {
+        assert_eq!(count(&[1, 3, 2, 4]), PathStats { received: 4, lost: 0 });
+        assert_eq!(count(&[1, 4, 2, 3, 5]), PathStats { received: 5, lost: 0 });
+        // a reordered duplicate is not taken off the loss twice
+        assert_eq!(count(&[1, 4, 2, 2, 5]), PathStats { received: 5, lost: 1 });
+    }
+
+    #[test]
+    fn path_counter_restarts_with_a_new_ssrc() {
+        let mut counter = PathCounter::default();
+        counter.count(1, 100);
+        counter.count(2, 7);
+        counter.count(2, 8);
+        assert_eq!(counter.stats, PathStats { received: 3, lost: 0 });
+    }
+}
@@ -0,0 +1,55 @@
+/// DSCP / QoS marking of the network traffic
+/// 
+/// managed switches prioritise the traffic by the DSCP value of the packets.
+/// the marking is configured by the `dscp` of the `RTPServerOptions` and the `PlaybackClientOptions`
+/// and used by every socket they open
+use std::net::UdpSocket;
+
+use gst::prelude::*;
+use log::warn;
+
+/// Expedited Forwarding, recommended for RTP
+pub const DSCP_EF: u8 = 46;
+/// Class Selector 3, recommended for RTCP, clock and discovery
+pub const DSCP_CS3: u8 = 24;
+
+/// DSCP values of the different kinds of traffic, `None` sends best effort
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DscpConfig {
+    /// RTP media packets
+    pub rtp: Option<u8>,
+    /// RTCP, clock, discovery and confirmation packets
+    pub control: Option<u8>,
+}
+
+impl DscpConfig {
+    /// EF for RTP and CS3 for everything else
+    pub fn recommended() -> Self {
+        DscpConfig {
+            rtp: Some(DSCP_EF),
+            control: Some(DSCP_CS3),
+        }
+    }
+}
+
+/// mark the packets of a socket with the DSCP value
+pub(crate) fn mark_socket(socket: &UdpSocket, dscp: Option<u8>) {
+    if let Some(dscp) = dscp {
+        // the DSCP are the upper 6 bits of the TOS byte
+        if let Err(e) = socket2::SockRef::from(socket).set_tos((dscp as u32) << 2) {
+            warn!("could not set dscp {} on socket: {:?}", dscp, e);
+        }
+    }
+}
+
+/// mark the packets of an element with the DSCP value, the element needs a `qos-dscp`
+/// property like udpsink, multiudpsink, NetTimeProvider or NetClientClock
+pub(crate) fn mark_object<O: IsA<gst::glib::Object>>(object: &O, dscp: Option<u8>) {
+    if let Some(dscp) = dscp {
+        if object.has_property("qos-dscp", None) {
+            object.set_property("qos-dscp", dscp as i32);
+        } else {
+            warn!("could not set dscp {} on {}, it has no qos-dscp property", dscp, object.type_().name());
+        }
+    }
+}
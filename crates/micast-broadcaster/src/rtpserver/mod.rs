@@ -1,25 +1,354 @@
 
-use std::net::IpAddr;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
 use std::time::Instant;
 
 use gst::prelude::*;
 use gst::glib;
 use gst_rtp::prelude::*;
 use std::sync::{Arc, Mutex};
-use log::{warn, debug,trace};
+use log::{warn, debug, info, trace};
 use crate::services;
+use crate::helpers::make_rtx_bin;
+use crate::sdp::{SessionDescription, MediaFormat};
+use crate::aes67::{Aes67Options, AES67_CLOCK_RATE, AES67_PTIME_MS};
+use crate::clock::grandmaster_clock_id;
+use crate::qos::{self, DscpConfig};
+use crate::services::sap::{SapAnnouncer, SapOptions, SAP_ADDRESS};
+use crate::services::control::{self, ControlCommand, CONTROL_PORT};
+use crate::services::report::{ClientReport, REPORT_MAGIC};
+use crate::services::auth;
+use crate::services::access::{CredentialProof, TokenStore};
+use crate::encryption::{self, KeyExchange, KeyProvider, KeyRing, SrtpCipherSuite, SrtpKey};
+use crate::encryption::exchange::PUBLIC_KEY_LENGTH;
+use crate::encryption::keyring::{shared_wrapping_key, wrap_key};
+
+/// id and uri of the header extension which carries the NTP send time of each packet
+const NTP64_EXTENSION_ID: u8 = 1;
+const NTP64_EXTENSION_URI: &str = "urn:ietf:params:rtp-hdrext:ntp-64";
+
+/// clients are removed in RTCP liveness mode if they send no RTCP packet in this time,
+/// RTCP packets are only send every few seconds
+const TIMEOUT_RTCP_IN_MS: u64 = 30000;
+
+/// how long the server waits for the clients to acknowledge a new SRTP key
+const KEY_DISTRIBUTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// rejected clients keep confirming, they are reported again after this interval
+const REJECTION_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// events which are not received yet
+const EVENT_QUEUE_LENGTH: usize = 64;
+
+/// payload type of the audio stream
+pub(crate) const PAYLOAD_TYPE: u32 = 96;
+/// payload type used for retransmitted packets (RFC 4588)
+pub(crate) const RTX_PAYLOAD_TYPE: u32 = 97;
+/// payload type used for ULPFEC packets (RFC 5109)
+pub(crate) const FEC_PAYLOAD_TYPE: u32 = 122;
+
+/// Forward error correction (ULPFEC) settings
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FecOptions {
+    /// amount of FEC packets in percent of the media packets
+    pub percentage: u32,
+    /// amount of FEC packets in percent for important media packets (marker bit set)
+    pub percentage_important: u32,
+}
+
+impl Default for FecOptions {
+    fn default() -> Self {
+        FecOptions {
+            percentage: 20,
+            percentage_important: 40,
+        }
+    }
+}
+
+/// Dual-path redundancy (SMPTE 2022-7 style), identical RTP packets are send over two paths
+/// 
+/// used by the `RTPServer` and the `PlaybackClient`, which merges both paths by sequence number
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedundancyOptions {
+    /// port of the second path, the first path uses the rtp port
+    pub secondary_port: u32,
+    /// network interface of the first path, e.g. eth0
+    pub primary_iface: Option<String>,
+    /// network interface of the second path, e.g. eth1
+    pub secondary_iface: Option<String>,
+    /// multicast group of the second path, required by the `RTPServer`.
+    /// the clients are only added to the first path, unicast to the same address would not be a second path
+    pub secondary_multicast_group: Option<String>,
+}
+
+impl Default for RedundancyOptions {
+    fn default() -> Self {
+        RedundancyOptions {
+            secondary_port: 5010,
+            primary_iface: None,
+            secondary_iface: None,
+            secondary_multicast_group: None,
+        }
+    }
+}
+
+/// Scheduled rotation of the SRTP key
+/// 
+/// the next key is distributed to the connected clients over the control channel
+/// and activated after the lead time, requires a shared secret (`services::auth`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyRotationOptions {
+    /// time between two key changes
+    pub interval: std::time::Duration,
+    /// time between the distribution and the activation of the next key
+    pub lead_time: std::time::Duration,
+}
+
+impl Default for KeyRotationOptions {
+    fn default() -> Self {
+        KeyRotationOptions {
+            interval: std::time::Duration::from_secs(3600),
+            lead_time: std::time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// How the RTP Server knows which clients are alive
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ClientLiveness {
+    /// clients send confirmation messages (`services::confirm`) to port 5887
+    #[default]
+    Confirm,
+    /// clients are added, refreshed and removed by their RTCP receiver reports and BYE packets,
+    /// requires `with_rtcp`
+    Rtcp,
+}
+
+/// Options for the RTP Server
+#[derive(Debug, Clone)]
+pub struct RTPServerOptions {
+    /// port where the clients receive the RTP stream, RTCP is send to `rtp_port + 1`
+    /// and the RTCP of the clients is received on `rtp_port + 2`
+    pub rtp_port: u32,
+    /// send RTCP packets to the clients and listen for their RTCP packets
+    pub with_rtcp: bool,
+    /// encode the stream as OPUS, otherwise L24 is send
+    pub as_opus: bool,
+    /// answer RTCP NACK requests of the clients with retransmissions (RFC 4588)
+    /// requires `with_rtcp`
+    pub retransmission: bool,
+    /// protect the stream with ULPFEC packets, prefered for multicast 
+    /// where retransmission does not scale
+    pub fec: Option<FecOptions>,
+    /// send the stream as AES67 (L24, 48kHz, 1ms packet time) to a multicast group,
+    /// the stream gets announced via SAP. `as_opus` is ignored in this mode
+    pub aes67: Option<Aes67Options>,
+    /// announce the SDP of the stream periodically via SAP (RFC 2974).
+    /// in AES67 mode the stream is always announced, per default on 239.255.255.255
+    pub sap: Option<SapOptions>,
+    /// how clients are added to and removed from the stream destinations
+    pub liveness: ClientLiveness,
+    /// send the RTP packets additionally over a second path
+    pub redundancy: Option<RedundancyOptions>,
+    /// SRTP key of the deployment, e.g. a [`crate::encryption::KeyFile`]
+    pub key_provider: Option<Arc<dyn KeyProvider>>,
+    /// rotate the SRTP key on a schedule, requires `key_provider`.
+    /// keys can also be rotated on demand with [`RTPServer::rotate_key`]
+    pub key_rotation: Option<KeyRotationOptions>,
+    /// encrypt the stream and RTCP with SRTP, requires `key_provider`.
+    /// not possible in AES67 mode and with SAP, the SDP would carry the key
+    pub encryption: bool,
+    /// crypto suite of SRTP and SRTCP, announced to the clients.
+    /// the key of `key_provider` needs the length of the suite
+    pub cipher_suite: SrtpCipherSuite,
+    /// agree a key with every client (X25519) in the confirmation handshake and send the session key
+    /// only to the registered clients, the stream is rekeyed if a client is removed.
+    /// requires `encryption`, a shared secret (`services::auth`) and [`ClientLiveness::Confirm`]
+    pub key_exchange: bool,
+    /// admit only clients with a credential of the store, rejected clients are reported as [`ServerEvent::ClientRejected`].
+    /// requires [`ClientLiveness::Confirm`]
+    pub token_store: Option<Arc<dyn TokenStore>>,
+    /// maximum number of concurrent clients, further clients are rejected
+    pub max_clients: Option<usize>,
+    /// fixed destination of the stream besides the clients, e.g. a multicast group.
+    /// it is the connection address of the SDP and the SAP announcement, in AES67 mode the multicast group is used
+    pub destination: Option<String>,
+    /// DSCP marking of the RTP packets and of the RTCP, clock, discovery and control packets
+    pub dscp: DscpConfig,
+}
+
+impl Default for RTPServerOptions {
+    fn default() -> Self {
+        RTPServerOptions {
+            rtp_port: 5000,
+            with_rtcp: true,
+            as_opus: true,
+            retransmission: false,
+            fec: None,
+            aes67: None,
+            sap: None,
+            liveness: ClientLiveness::default(),
+            redundancy: None,
+            key_provider: None,
+            key_rotation: None,
+            encryption: false,
+            cipher_suite: SrtpCipherSuite::default(),
+            key_exchange: false,
+            token_store: None,
+            max_clients: None,
+            destination: None,
+            dscp: DscpConfig::default(),
+        }
+    }
+}
+
+/// Why a client was not admitted
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// the confirmation has no proof of a token
+    MissingCredential,
+    /// the token store does not know the credential, or it was revoked
+    UnknownCredential,
+    /// the proof of the token is invalid, too old or replayed
+    InvalidProof,
+    /// the token store could not be read
+    StoreError(String),
+    /// `max_clients` clients are connected
+    TooManyClients,
+}
+
+/// Events of the RTP Server, see [`RTPServer::events`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// a client was not admitted or removed because its credential was revoked
+    ClientRejected { addr: IpAddr, reason: RejectReason },
+}
+
+/// Counters of RFC 4588 retransmissions
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RetransmissionStats {
+    /// number of retransmission requests (NACKs) 
+    pub requests: u32,
+    /// number of retransmitted packets
+    pub packets: u32,
+}
 
 #[derive(Debug,Clone)]
 pub struct RTPClient {
     addr: IpAddr,
     last_connection: Instant,
     name: String,
+    ssrc: Option<u32>,
+    report: Option<(Instant, ClientReport)>,
+    /// public key of the key exchange from the confirmations
+    public_key: Option<[u8; PUBLIC_KEY_LENGTH]>,
+    /// port of the control listener from the confirmations, per default [`CONTROL_PORT`]
+    control_port: u16,
+}
+
+impl RTPClient {
+    /// where the commands for the client are send to
+    fn control_addr(&self) -> SocketAddr {
+        (self.addr, self.control_port).into()
+    }
+}
+
+/// Admission of new clients by their credential and the maximum number of clients
+#[derive(Debug)]
+struct Admission {
+    token_store: Option<Arc<dyn TokenStore>>,
+    max_clients: Option<usize>,
+    events: crossbeam_channel::Sender<ServerEvent>,
+    /// when the rejected clients were reported
+    rejected: Mutex<HashMap<IpAddr, Instant>>,
+}
+
+impl Admission {
+    /// check the credential against the token store
+    ///
+    /// # Returns
+    /// the name of the client from the token store, empty without token store
+    fn authorize(&self, proof: Option<&CredentialProof>) -> Result<String, RejectReason> {
+        let token_store = match &self.token_store {
+            Some(token_store) => token_store,
+            None => return Ok(String::new()),
+        };
+        let proof = proof.ok_or(RejectReason::MissingCredential)?;
+
+        let (credential, name) = match token_store.lookup(&proof.id) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Err(RejectReason::UnknownCredential),
+            Err(e) => return Err(RejectReason::StoreError(e.to_string())),
+        };
+        proof.verify(&credential).map_err(|e| {
+            debug!("invalid proof of credential {}: {}", proof.id, e);
+            RejectReason::InvalidProof
+        })?;
+        Ok(name)
+    }
+
+    /// check the number of connected clients for a new client
+    ///
+    /// # Arguments
+    /// * `authorization` - the result of [`Admission::authorize`] for the client
+    fn admit(&self, addr: IpAddr, authorization: Result<String, RejectReason>, connected: usize) -> Option<String> {
+        let admitted = authorization.and_then(|name| match self.max_clients {
+            Some(max_clients) if connected >= max_clients => Err(RejectReason::TooManyClients),
+            _ => Ok(name),
+        });
+
+        match admitted {
+            Ok(name) => {
+                self.rejected.lock().unwrap().remove(&addr);
+                Some(name)
+            },
+            Err(reason) => {
+                self.reject(addr, reason);
+                None
+            }
+        }
+    }
+
+    /// report the rejected client, at most once per [`REJECTION_REPORT_INTERVAL`]
+    fn reject(&self, addr: IpAddr, reason: RejectReason) {
+        let mut rejected = self.rejected.lock().unwrap();
+        rejected.retain(|_, reported| reported.elapsed() < REJECTION_REPORT_INTERVAL);
+        if rejected.contains_key(&addr) {
+            trace!("client {} rejected again: {:?}", addr, reason);
+            return;
+        }
+        rejected.insert(addr, Instant::now());
+        drop(rejected);
+
+        warn!("reject client {}: {:?}", addr, reason);
+        // nobody may listen for the events
+        let _ = self.events.try_send(ServerEvent::ClientRejected { addr, reason });
+    }
+}
+
+/// Status of a connected client with its last report
+#[derive(Debug, Clone)]
+pub struct ClientStatus {
+    pub addr: IpAddr,
+    /// name of the client from the token store, empty without token store
+    pub name: String,
+    /// time since the last confirmation, RTCP packet or report
+    pub last_seen: std::time::Duration,
+    /// the last report of the client and its age, `None` if the client sends no reports
+    pub report: Option<(std::time::Duration, ClientReport)>,
 }
 
 #[derive(Debug,Clone)]
 pub struct RTPServer {
     bin: gst::Bin,
+    options: RTPServerOptions,
+    session_id: u64,
+    #[allow(dead_code)]
+    sap_announcer: Option<Arc<SapAnnouncer>>,
     connected_clients: Arc<Mutex<Vec<RTPClient>>>,
+    keyring: Option<Arc<KeyRing>>,
+    key_exchange: Option<Arc<KeyExchange>>,
+    admission: Arc<Admission>,
+    events: crossbeam_channel::Receiver<ServerEvent>,
     rtcp_receiver: Option<gst::Element>,
     stop_sender: crossbeam_channel::Sender<bool>,
     pub client_receiver: crossbeam_channel::Receiver<(IpAddr, String)>,
@@ -31,18 +360,163 @@ unsafe impl Sync for RTPServer {}
 
 impl RTPServer {
     pub fn new(with_rtcp: bool, as_opus: bool) -> Result<RTPServer, anyhow::Error> {
+        RTPServer::with_options(RTPServerOptions { 
+            with_rtcp, 
+            as_opus, 
+            ..Default::default() 
+        })
+    }
+
+    /// Create the RTP Server with all available options
+    pub fn with_options(options: RTPServerOptions) -> Result<RTPServer, anyhow::Error> {
 
-        let bin = RTPServer::_prepare_bin(with_rtcp, as_opus)?;
+        // the NACKs of the clients arrive as RTCP packets
+        if options.retransmission && !options.with_rtcp {
+            return Err(anyhow::anyhow!("retransmission requires rtcp"));
+        }
+
+        let keyring = match &options.key_provider {
+            Some(provider) => Some(Arc::new(KeyRing::from_provider(provider, options.cipher_suite)?)),
+            None => None,
+        };
 
-        let rtcp_receiver = if with_rtcp {
+        if options.encryption {
+            if keyring.is_none() {
+                return Err(anyhow::anyhow!("srtp encryption requires a key provider"));
+            }
+            if options.aes67.is_some() || options.sap.is_some() {
+                return Err(anyhow::anyhow!("srtp encryption is not possible with aes67 or sap"));
+            }
+            if !options.cipher_suite.is_available() {
+                return Err(anyhow::anyhow!("srtp crypto suite {} is not supported by the installed srtpenc", options.cipher_suite));
+            }
+        }
+
+        let key_exchange = match (options.key_exchange, &keyring) {
+            (false, _) => None,
+            (true, Some(keyring)) if options.encryption && options.liveness == ClientLiveness::Confirm => {
+                // without signatures the public keys could be replaced
+                if !auth::is_enabled() {
+                    return Err(anyhow::anyhow!("the key exchange requires a shared secret"));
+                }
+                // the clients only get the session key with the key exchange, never the key of the provider
+                let session_key = keyring.generate()?;
+                keyring.activate(&session_key.mki)?;
+                Some(Arc::new(KeyExchange::new()?))
+            },
+            (true, _) => return Err(anyhow::anyhow!("the key exchange requires encryption and confirmed clients")),
+        };
+
+        if let Some(RedundancyOptions { secondary_multicast_group: None, .. }) = &options.redundancy {
+            return Err(anyhow::anyhow!("redundancy requires the multicast group of the second path"));
+        }
+
+        if options.token_store.is_some() && options.liveness != ClientLiveness::Confirm {
+            return Err(anyhow::anyhow!("the token store requires confirmed clients"));
+        }
+        let (events_sender, events) = crossbeam_channel::bounded(EVENT_QUEUE_LENGTH);
+        let admission = Arc::new(Admission {
+            token_store: options.token_store.clone(),
+            max_clients: options.max_clients,
+            events: events_sender,
+            rejected: Mutex::new(HashMap::new()),
+        });
+
+        let encryption_keyring = keyring.as_ref().filter(|_| options.encryption);
+        let bin = RTPServer::_prepare_bin(&options, encryption_keyring)?;
+
+        let rtcp_receiver = if options.with_rtcp {
             bin.by_name("udprtscpsrc0")
         } else { None };
 
-        let (client_receiver, stop_sender) = services::thread_for_confirm().unwrap();
+        let (client_receiver, stop_sender) = match options.liveness {
+            ClientLiveness::Confirm => services::thread_for_confirm().unwrap(),
+            ClientLiveness::Rtcp => {
+                // no confirmations are received in this mode
+                let (_, client_receiver) = crossbeam_channel::unbounded();
+                let (stop_sender, _) = crossbeam_channel::unbounded();
+                (client_receiver, stop_sender)
+            }
+        };
 
         let connected_clients = Arc::new(Mutex::new(Vec::new()));
 
-        Ok(RTPServer { bin, rtcp_receiver, client_receiver, stop_sender, connected_clients })
+        if options.liveness == ClientLiveness::Rtcp {
+            Self::_connect_rtcp_liveness(&bin, connected_clients.clone(), keyring.clone(), admission.clone(), options.rtp_port as i32, options.dscp.control)?;
+        }
+
+        if let (Some(rotation), Some(keyring)) = (options.key_rotation, &keyring) {
+            Self::_schedule_key_rotation(rotation, Arc::downgrade(keyring), key_exchange.clone(), Arc::downgrade(&connected_clients), options.dscp.control);
+        }
+
+        let session_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        if let Some(aes67) = &options.aes67 {
+            let port = options.rtp_port as i32;
+            if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
+                debug!("send aes67 stream to {}:{}", aes67.multicast_group, port);
+                rtp_udp_sink.emit_by_name::<()>("add", &[&aes67.multicast_group, &port]);
+            }
+            if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
+                rtcp_udp_sink.emit_by_name::<()>("add", &[&aes67.multicast_group, &(port + 1)]);
+            }
+        }
+
+        let secondary = options.redundancy.as_ref()
+            .and_then(|redundancy| Some((redundancy.secondary_multicast_group.as_ref()?, redundancy.secondary_port as i32)));
+        if let (Some((group, port)), Some(rtp_udp_sink)) = (secondary, bin.by_name("rtpsink1")) {
+            debug!("send the second path to {}:{}", group, port);
+            rtp_udp_sink.emit_by_name::<()>("add", &[group, &port]);
+        }
+
+        if let (Some(destination), None) = (&options.destination, &options.aes67) {
+            let port = options.rtp_port as i32;
+            if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
+                debug!("send stream to {}:{}", destination, port);
+                rtp_udp_sink.emit_by_name::<()>("add", &[destination, &port]);
+            }
+            if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
+                rtcp_udp_sink.emit_by_name::<()>("add", &[destination, &(port + 1)]);
+            }
+        }
+
+        let sap_options = match (&options.sap, &options.aes67) {
+            (Some(sap), _) => Some(sap.clone()),
+            (None, Some(_)) => Some(SapOptions { address: SAP_ADDRESS, ..Default::default() }),
+            (None, None) => None,
+        };
+
+        let sap_announcer = if let Some(sap_options) = sap_options {
+            let sdp = Self::_session_description(&bin, &options, session_id)?.to_string();
+            let sap_announcer = Arc::new(SapAnnouncer::new(sdp, sap_options.address, sap_options.interval, options.dscp.control));
+
+            // clients and the ptp grandmaster may change, so update the sdp
+            let weak_bin = bin.downgrade();
+            let weak_sap_announcer = Arc::downgrade(&sap_announcer);
+            let cloned_options = options.clone();
+            glib::timeout_add(sap_options.interval, move || {
+                let bin = match weak_bin.upgrade() {
+                    Some(bin) => bin,
+                    None => return glib::Continue(false),
+                };
+                let sap_announcer = match weak_sap_announcer.upgrade() {
+                    Some(sap_announcer) => sap_announcer,
+                    None => return glib::Continue(false),
+                };
+                match Self::_session_description(&bin, &cloned_options, session_id) {
+                    Ok(description) => sap_announcer.set_sdp(description.to_string()),
+                    Err(e) => warn!("could not update the sap announcement: {}", e),
+                }
+                glib::Continue(true)
+            });
+
+            Some(sap_announcer)
+        } else { None };
+
+        Ok(RTPServer { bin, options, session_id, sap_announcer, rtcp_receiver, client_receiver, stop_sender, connected_clients, keyring, key_exchange, admission, events })
 
     }
 
@@ -55,6 +529,262 @@ impl RTPServer {
         self.bin.static_pad("sink")
     }
 
+    /// Counters of the retransmission sender, `None` if retransmission is not enabled
+    /// or no client requested a packet yet
+    pub fn retransmission_stats(&self) -> Option<RetransmissionStats> {
+        let rtxsend = self.bin.by_name("rtxsend0")?;
+
+        Some(RetransmissionStats {
+            requests: rtxsend.property::<u32>("num-rtx-requests"),
+            packets: rtxsend.property::<u32>("num-rtx-packets"),
+        })
+    }
+
+    /// Addresses of the connected clients
+    pub fn clients(&self) -> Vec<IpAddr> {
+        self.connected_clients.lock().unwrap().iter().map(|c| c.addr).collect()
+    }
+
+    /// Status of the connected clients with their last reports,
+    /// reports are only received if the clients are confirmed ([`ClientLiveness::Confirm`])
+    pub fn client_status(&self) -> Vec<ClientStatus> {
+        self.connected_clients.lock().unwrap().iter().map(|c| ClientStatus {
+            addr: c.addr,
+            name: c.name.clone(),
+            last_seen: c.last_connection.elapsed(),
+            report: c.report.as_ref().map(|(received, report)| (received.elapsed(), report.clone())),
+        }).collect()
+    }
+
+    /// Send a command to all connected clients and wait for the acknowledgements
+    /// 
+    /// # Returns
+    /// the result of every client, an error if the client did not acknowledge the command
+    /// or could not execute it
+    pub fn send_command(&self, command: &ControlCommand, timeout: std::time::Duration) -> Vec<(IpAddr, Result<(), anyhow::Error>)> {
+        let commands = self.connected_clients.lock().unwrap().iter().map(|c| (c.control_addr(), command.clone())).collect();
+        Self::_send_to_clients(commands, timeout, self.options.dscp.control)
+    }
+
+    fn _send_to_clients(commands: Vec<(SocketAddr, ControlCommand)>, timeout: std::time::Duration, dscp: Option<u8>) -> Vec<(IpAddr, Result<(), anyhow::Error>)> {
+        std::thread::scope(|scope| {
+            let handles: Vec<_> = commands.into_iter()
+                .map(|(client, command)| (client.ip(), scope.spawn(move || control::send_command(client, &command, timeout, dscp))))
+                .collect();
+
+            handles.into_iter()
+                .map(|(client, handle)| (client, handle.join().unwrap_or_else(|_| Err(anyhow::anyhow!("sending the command panicked")))))
+                .collect()
+        })
+    }
+
+    /// Events of the server, e.g. rejected clients
+    ///
+    /// the queue holds the latest events, further events are dropped until they are received
+    pub fn events(&self) -> crossbeam_channel::Receiver<ServerEvent> {
+        self.events.clone()
+    }
+
+    /// The keys of the SRTP key rotation, `None` without `key_provider`
+    pub fn keyring(&self) -> Option<Arc<KeyRing>> {
+        self.keyring.clone()
+    }
+
+    /// Switch to a new SRTP key now
+    /// 
+    /// the key is distributed to the connected clients and activated after they acknowledged it.
+    /// requires a `key_provider` and a shared secret (`services::auth`)
+    /// 
+    /// # Returns
+    /// the result of the distribution to every client, clients which did not get the key
+    /// can not decrypt the stream anymore
+    pub fn rotate_key(&self) -> Result<Vec<(IpAddr, Result<(), anyhow::Error>)>, anyhow::Error> {
+        let keyring = self.keyring.as_ref().ok_or_else(|| anyhow::anyhow!("srtp is not configured"))?;
+        let clients = Self::_client_keys(&self.connected_clients);
+        Self::_rotate_key(keyring, self.key_exchange.as_deref(), clients, std::time::Duration::ZERO, self.options.dscp.control)
+    }
+
+    /// control addresses and public keys of the connected clients
+    fn _client_keys(connected_clients: &Mutex<Vec<RTPClient>>) -> Vec<(SocketAddr, Option<[u8; PUBLIC_KEY_LENGTH]>)> {
+        connected_clients.lock().unwrap().iter().map(|c| (c.control_addr(), c.public_key)).collect()
+    }
+
+    /// the key wrapped for one client, with the key agreed with the client or the shared secret
+    fn _key_command(key_exchange: Option<&KeyExchange>, client_key: Option<&[u8; PUBLIC_KEY_LENGTH]>, key: &SrtpKey) -> Result<ControlCommand, anyhow::Error> {
+        let (wrapping_key, server_key) = match key_exchange {
+            Some(key_exchange) => {
+                let client_key = client_key.ok_or_else(|| anyhow::anyhow!("the client sent no public key"))?;
+                (key_exchange.wrapping_key(client_key)?, Some(key_exchange.public_key().to_vec()))
+            },
+            None => (shared_wrapping_key()?, None),
+        };
+
+        Ok(ControlCommand::AddKey { mki: key.mki.clone(), wrapped: wrap_key(&wrapping_key, key)?, server_key })
+    }
+
+    fn _rotate_key(keyring: &KeyRing, key_exchange: Option<&KeyExchange>, clients: Vec<(SocketAddr, Option<[u8; PUBLIC_KEY_LENGTH]>)>, lead_time: std::time::Duration, dscp: Option<u8>) -> Result<Vec<(IpAddr, Result<(), anyhow::Error>)>, anyhow::Error> {
+        if key_exchange.is_none() {
+            // fail before a key is generated
+            shared_wrapping_key()?;
+        }
+        let next = keyring.generate()?;
+
+        let mut results = Vec::new();
+        let mut commands = Vec::new();
+        for (client, client_key) in clients {
+            match Self::_key_command(key_exchange, client_key.as_ref(), &next) {
+                Ok(command) => commands.push((client, command)),
+                Err(e) => results.push((client.ip(), Err(e))),
+            }
+        }
+        results.extend(Self::_send_to_clients(commands, KEY_DISTRIBUTION_TIMEOUT, dscp));
+
+        for (client, result) in &results {
+            if let Err(e) = result {
+                warn!("could not distribute srtp key {:?} to {}: {}", next, client, e);
+            }
+        }
+
+        std::thread::sleep(lead_time);
+        keyring.activate(&next.mki)?;
+        Ok(results)
+    }
+
+    fn _schedule_key_rotation(rotation: KeyRotationOptions, keyring: std::sync::Weak<KeyRing>, key_exchange: Option<Arc<KeyExchange>>, connected_clients: std::sync::Weak<Mutex<Vec<RTPClient>>>, dscp: Option<u8>) {
+        std::thread::spawn(move || loop {
+            std::thread::sleep(rotation.interval.saturating_sub(rotation.lead_time));
+
+            // the server is dropped
+            let (keyring, connected_clients) = match (keyring.upgrade(), connected_clients.upgrade()) {
+                (Some(keyring), Some(connected_clients)) => (keyring, connected_clients),
+                _ => break,
+            };
+
+            let clients = Self::_client_keys(&connected_clients);
+            if let Err(e) = Self::_rotate_key(&keyring, key_exchange.as_deref(), clients, rotation.lead_time, dscp) {
+                warn!("srtp key rotation failed: {}", e);
+            }
+        });
+    }
+
+    /// a new client only knows the key of the key provider, so send it the newer keys
+    fn _send_keys(keyring: &Option<Arc<KeyRing>>, key_exchange: &Option<Arc<KeyExchange>>, client: SocketAddr, client_key: Option<[u8; PUBLIC_KEY_LENGTH]>, dscp: Option<u8>) {
+        let keyring = match keyring {
+            Some(keyring) if !keyring.is_initial() => keyring.clone(),
+            _ => return,
+        };
+        let key_exchange = key_exchange.clone();
+
+        std::thread::spawn(move || {
+            for key in keyring.distributed_keys() {
+                let result = Self::_key_command(key_exchange.as_deref(), client_key.as_ref(), &key)
+                    .and_then(|command| control::send_command(client, &command, KEY_DISTRIBUTION_TIMEOUT, dscp));
+                if let Err(e) = result {
+                    warn!("could not send srtp key {:?} to {}: {}", key, client, e);
+                }
+            }
+        });
+    }
+
+    /// with the key exchange a removed client must not decrypt the stream anymore,
+    /// so the remaining clients get a new key
+    fn _rekey(keyring: &Option<Arc<KeyRing>>, key_exchange: &Option<Arc<KeyExchange>>, connected_clients: &Arc<Mutex<Vec<RTPClient>>>, dscp: Option<u8>) {
+        let (keyring, key_exchange) = match (keyring, key_exchange) {
+            (Some(keyring), Some(key_exchange)) => (keyring.clone(), key_exchange.clone()),
+            _ => return,
+        };
+        let clients = Self::_client_keys(connected_clients);
+
+        std::thread::spawn(move || {
+            info!("rekey srtp for {} remaining clients", clients.len());
+            if let Err(e) = Self::_rotate_key(&keyring, Some(&key_exchange), clients, std::time::Duration::ZERO, dscp) {
+                warn!("srtp rekey failed: {}", e);
+            }
+        });
+    }
+
+    /// Describes the RTP stream as SDP, so other players like VLC, ffplay or gst-launch
+    /// can receive the stream
+    /// 
+    /// the destination is the AES67 multicast group or `destination` of the options,
+    /// fails if the stream has none. fails with the key exchange too, the SDP would carry the session key
+    pub fn sdp(&self) -> Result<String, anyhow::Error> {
+        Ok(self.session_description()?.to_string())
+    }
+
+    /// Writes the SDP of the RTP stream to a file, see [`RTPServer::sdp`]
+    pub fn write_sdp<P: AsRef<std::path::Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        std::fs::write(path, self.sdp()?)?;
+        Ok(())
+    }
+
+    pub(crate) fn session_description(&self) -> Result<SessionDescription, anyhow::Error> {
+        // the session key is only for the clients of the key exchange, removed clients are locked out by a rekey
+        if self.key_exchange.is_some() {
+            return Err(anyhow::anyhow!("no sdp with the key exchange, it would expose the session key"));
+        }
+
+        let mut description = Self::_session_description(&self.bin, &self.options, self.session_id)?;
+        if self.options.encryption {
+            description.crypto = self.keyring.as_ref().map(|keyring| keyring.crypto_attribute());
+        }
+        Ok(description)
+    }
+
+    fn _session_description(bin: &gst::Bin, options: &RTPServerOptions, session_id: u64) -> Result<SessionDescription, anyhow::Error> {
+        let mut formats = if let Some(aes67) = &options.aes67 {
+            vec![MediaFormat::new(aes67.payload_type, "L24", AES67_CLOCK_RATE as u32, Some(aes67.channels as u32))]
+        } else if options.as_opus {
+            vec![MediaFormat::new(PAYLOAD_TYPE, "OPUS", 48000, Some(2))]
+        } else {
+            vec![MediaFormat::new(PAYLOAD_TYPE, "L24", 48000, Some(2))]
+        };
+
+        if options.retransmission {
+            formats.push(
+                MediaFormat::new(RTX_PAYLOAD_TYPE, "rtx", 48000, None)
+                    .with_fmtp(&format!("apt={}", PAYLOAD_TYPE))
+            );
+        }
+
+        if options.fec.is_some() {
+            formats.push(MediaFormat::new(FEC_PAYLOAD_TYPE, "ulpfec", 48000, None));
+        }
+
+        // the clients are not a destination for other players, the first one is the local player
+        let destination = options.aes67.as_ref()
+            .map(|aes67| aes67.multicast_group.clone())
+            .or_else(|| options.destination.clone())
+            .ok_or_else(|| anyhow::anyhow!("the sdp needs a destination, set the aes67 multicast group or destination"))?;
+
+        let origin_address = local_ip_address::local_ip()
+            .map(|ip| ip.to_string())
+            .unwrap_or_else(|_| "127.0.0.1".to_string());
+
+        let mut description = SessionDescription {
+            session_id,
+            origin_address,
+            session_name: "micast-dj".to_string(),
+            connection_address: destination,
+            ttl: None,
+            port: options.rtp_port,
+            // the rtcp of the receivers is received on the port after the one of the clients
+            rtcp_port: Some(options.rtp_port + 2),
+            formats,
+            extensions: vec![(NTP64_EXTENSION_ID, NTP64_EXTENSION_URI.to_string())],
+            crypto: None,
+            attributes: Vec::new(),
+        };
+
+        if let Some(aes67) = &options.aes67 {
+            let grandmaster_clock_id = bin.clock().and_then(|clock| grandmaster_clock_id(&clock));
+            description.ttl = Some(aes67.ttl);
+            description.attributes = aes67.sdp_attributes(grandmaster_clock_id);
+        }
+
+        Ok(description)
+    }
+
     pub fn set_listen_for_rtcp_packets(&self, port: i32) -> Result<(), anyhow::Error> {
         debug!("enable listen for rtcp packets");
         if let Some(rtcp_receiver) = &self.rtcp_receiver {
@@ -124,7 +854,7 @@ impl RTPServer {
     ///
     /// The configured `gst::Element` or an `anyhow::Error` if an error occurred during creation or configuration.
     ///
-    fn _set_udpsink(mcast: bool, is_rtp: bool) -> Result<gst::Element, anyhow::Error> {
+    fn _set_udpsink(mcast: bool, is_rtp: bool, dscp: &DscpConfig) -> Result<gst::Element, anyhow::Error> {
         let prop_name = if is_rtp { "rtpsink0" } else { "rtcpsink0" };
         let udpsink = gst::ElementFactory::make_with_name("multiudpsink", Some(prop_name))
             .map_err(|_| anyhow::anyhow!("Failed to create multiudpsink element"))?;
@@ -141,6 +871,12 @@ impl RTPServer {
             udpsink.set_property("ttl-mc", 1i32);
         }
 
+        if is_rtp {
+            qos::mark_object(&udpsink, dscp.rtp);
+        } else {
+            qos::mark_object(&udpsink, dscp.control);
+        }
+
         if is_rtp {
             udpsink.set_property("sync", true);
         } else {
@@ -153,6 +889,24 @@ impl RTPServer {
         Ok(udpsink)
     }
 
+    /// send the packets of the `multiudpsink` over the network interface
+    fn _set_iface(udpsink: &gst::Element, iface: Option<&str>) {
+        let iface = match iface {
+            Some(iface) => iface,
+            None => return,
+        };
+
+        udpsink.set_property("multicast-iface", iface);
+
+        let address = local_ip_address::list_afinet_netifas()
+            .ok()
+            .and_then(|ifas| ifas.into_iter().find(|(name, ip)| name == iface && ip.is_ipv4()));
+        match address {
+            Some((_, ip)) => udpsink.set_property("bind-address", ip.to_string()),
+            None => warn!("no address found for interface {}", iface),
+        }
+    }
+
     /// set UDPSRC for receiving rtcp packets
     /// 
     fn _set_udpsrc(rtcp_receiving_port: u32) -> Result<gst::Element, anyhow::Error> {
@@ -168,10 +922,127 @@ impl RTPServer {
     }
 
 
+    /// add, refresh and remove clients by their RTCP packets 
+    fn _connect_rtcp_liveness(bin: &gst::Bin, connected_clients: Arc<Mutex<Vec<RTPClient>>>, keyring: Option<Arc<KeyRing>>, admission: Arc<Admission>, rtp_port: i32, dscp: Option<u8>) -> Result<(), anyhow::Error> {
+        let rtpbin = bin.by_name("RTPBin0").ok_or_else(|| anyhow::anyhow!("RTPBin0 not found"))?;
+
+        // receiver reports of new and known clients
+        for signal in ["on-new-ssrc", "on-ssrc-active"] {
+            let weak_bin = bin.downgrade();
+            let connected_clients = connected_clients.clone();
+            let keyring = keyring.clone();
+            let admission = admission.clone();
+            rtpbin.connect(signal, true, move |data| {
+                let bin = weak_bin.upgrade()?;
+                let rtpbin = data[0].get::<gst::Element>().unwrap();
+                let sid = data[1].get::<u32>().unwrap();
+                let ssrc = data[2].get::<u32>().unwrap();
+
+                let addr = match Self::_rtcp_source_address(&rtpbin, sid, ssrc) {
+                    Some(addr) => addr,
+                    None => return None,
+                };
+
+                let mut clients = connected_clients.lock().unwrap();
+                if let Some(c) = clients.iter_mut().find(|c| c.addr == addr) {
+                    trace!("rtcp from connected client: {} {}", addr, ssrc);
+                    c.last_connection = Instant::now();
+                    c.ssrc = Some(ssrc);
+                    return None;
+                }
+
+                // RTCP carries no credential, only the number of clients is limited
+                let name = admission.admit(addr, admission.authorize(None), clients.len())?;
+
+                warn!("add client by rtcp: {} {}", addr, rtp_port);
+                Self::_add_destination(&bin, &addr.to_string(), rtp_port);
+                Self::_send_keys(&keyring, &None, (addr, CONTROL_PORT).into(), None, dscp);
+                clients.push(RTPClient { 
+                    addr, 
+                    last_connection: Instant::now(), 
+                    name,
+                    ssrc: Some(ssrc),
+                    report: None,
+                    public_key: None,
+                    control_port: CONTROL_PORT,
+                });
+                None
+            });
+        }
+
+        // BYE or no RTCP packets for a long time
+        for signal in ["on-bye-ssrc", "on-timeout"] {
+            let weak_bin = bin.downgrade();
+            let connected_clients = connected_clients.clone();
+            rtpbin.connect(signal, true, move |data| {
+                let bin = weak_bin.upgrade()?;
+                let ssrc = data[2].get::<u32>().unwrap();
+
+                connected_clients.lock().unwrap().retain(|c| {
+                    if c.ssrc == Some(ssrc) {
+                        warn!("removing {} cause of rtcp bye or timeout", c.addr);
+                        Self::_remove_destination(&bin, &c.addr.to_string(), rtp_port);
+                        false
+                    } else {
+                        true
+                    }
+                });
+                None
+            });
+        }
+
+        Ok(())
+    }
+
+    /// address where the RTCP packets of a remote source comes from, 
+    /// `None` for the own source of the server
+    fn _rtcp_source_address(rtpbin: &gst::Element, sid: u32, ssrc: u32) -> Option<IpAddr> {
+        let session = rtpbin.emit_by_name::<glib::Object>("get-internal-session", &[&sid]);
+        let source = session.emit_by_name::<Option<glib::Object>>("get-source-by-ssrc", &[&ssrc])?;
+        let stats = source.property::<gst::Structure>("stats");
+
+        if stats.get::<bool>("internal").unwrap_or(true) {
+            return None;
+        }
+
+        // rtcp-from is ip:port
+        let rtcp_from = stats.get::<String>("rtcp-from").ok()?;
+        rtcp_from.parse::<std::net::SocketAddr>().ok().map(|addr| addr.ip())
+    }
+
+    /// add a destination to the RTP and RTCP sinks, the second path is send to its multicast group only
+    fn _add_destination(bin: &gst::Bin, host: &str, rtp_port: i32) {
+        if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
+            rtp_udp_sink.emit_by_name::<()>("add", &[&host, &rtp_port]);
+        }
+        if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
+            rtcp_udp_sink.emit_by_name::<()>("add", &[&host, &(rtp_port + 1)]);
+        }
+    }
+
+    /// remove a destination from the RTP and RTCP sinks
+    fn _remove_destination(bin: &gst::Bin, host: &str, rtp_port: i32) {
+        if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
+            rtp_udp_sink.emit_by_name::<()>("remove", &[&host, &rtp_port]);
+        }
+        if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
+            rtcp_udp_sink.emit_by_name::<()>("remove", &[&host, &(rtp_port + 1)]);
+        }
+    }
+
     pub fn check_clients(&self) {
         let weak_bin = self.bin.downgrade();
         let cloned_receiver = self.client_receiver.clone();
         let connected_clients = self.connected_clients.clone();
+        let keyring = self.keyring.clone();
+        let key_exchange = self.key_exchange.clone();
+        let admission = self.admission.clone();
+        let rtp_port = self.options.rtp_port as i32;
+        let dscp = self.options.dscp.control;
+        let timeout_in_ms = match self.options.liveness {
+            ClientLiveness::Confirm => services::TIMEOUT_CONFIRM_IN_MS,
+            ClientLiveness::Rtcp => TIMEOUT_RTCP_IN_MS,
+        };
         glib::timeout_add(std::time::Duration::from_millis(300), move || {
             
             let bin = match weak_bin.upgrade() {
@@ -179,45 +1050,96 @@ impl RTPServer {
                 None => return glib::Continue(true),
             };
 
-            if let Ok((client, _name)) = cloned_receiver.try_recv() {
-                trace!("msg from client: {} {}", client, 5000);
+            while let Ok((client, message)) = cloned_receiver.try_recv() {
+                trace!("msg from client: {} {}", client, rtp_port);
+
+                let report = if message.starts_with(REPORT_MAGIC) {
+                    match message.parse::<ClientReport>() {
+                        Ok(report) => Some((Instant::now(), report)),
+                        Err(e) => {
+                            warn!("invalid report from {}: {}", client, e);
+                            None
+                        }
+                    }
+                } else {
+                    None
+                };
+                let public_key = services::confirmation_key(&message);
+                let proof = services::confirmation_proof(&message);
+                let control_port = services::confirmation_control_port(&message);
+                // the token store may read its file, so the lookup is done before the clients are locked
+                let authorization = admission.authorize(proof.as_ref());
 
-                if let Some(c) = connected_clients.lock().unwrap().iter_mut().find(|c| c.addr == client) {
+                let mut clients = connected_clients.lock().unwrap();
+                if let Some(position) = clients.iter().position(|c| c.addr == client) {
                     trace!("client already connected: {}", client);
+
+                    // revoked credentials are rejected with the next confirmation, reports carry no credential
+                    if report.is_none() && admission.token_store.is_some() {
+                        if let Err(reason) = authorization {
+                            clients.remove(position);
+                            drop(clients);
+                            Self::_remove_destination(&bin, &client.to_string(), rtp_port);
+                            admission.reject(client, reason);
+                            Self::_rekey(&keyring, &key_exchange, &connected_clients, dscp);
+                            continue;
+                        }
+                    }
+
+                    let c = &mut clients[position];
                     c.last_connection = Instant::now();
-                    return glib::Continue(true);
+                    if report.is_some() {
+                        c.report = report;
+                    }
+                    // the client restarted with a new key pair or control port, reports carry neither
+                    let new_key = public_key.is_some() && c.public_key != public_key;
+                    let new_control_port = report.is_none() && c.control_port != control_port.unwrap_or(CONTROL_PORT);
+                    if new_key || new_control_port {
+                        if new_key {
+                            c.public_key = public_key;
+                        }
+                        c.control_port = control_port.unwrap_or(CONTROL_PORT);
+                        Self::_send_keys(&keyring, &key_exchange, c.control_addr(), c.public_key, dscp);
+                    }
+                    continue;
                 } 
 
-                if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
-                    warn!("add client: {} {}", client, 5000);
-                    rtp_udp_sink.emit_by_name::<()>("add", &[&client.to_string(), &5000i32]);
-                } 
-                if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
-                    rtcp_udp_sink.emit_by_name::<()>("add", &[&client.to_string(), &5001i32]);
+                // only confirmations carry the credential
+                if report.is_some() && admission.token_store.is_some() {
+                    trace!("ignore report of unknown client {}", client);
+                    continue;
                 }
+                let name = match admission.admit(client, authorization, clients.len()) {
+                    Some(name) => name,
+                    None => continue,
+                };
+
+                warn!("add client: {} {}", client, rtp_port);
+                Self::_add_destination(&bin, &client.to_string(), rtp_port);
+                Self::_send_keys(&keyring, &key_exchange, (client, control_port.unwrap_or(CONTROL_PORT)).into(), public_key, dscp);
 
-                connected_clients.lock().unwrap().push(RTPClient { 
+                clients.push(RTPClient { 
                     addr: client, 
                     last_connection: Instant::now(), 
-                    name: "".to_string() 
+                    name,
+                    ssrc: None,
+                    report,
+                    public_key,
+                    control_port: control_port.unwrap_or(CONTROL_PORT),
                 });
             }
 
             // remove clients if they are not connected anymore
+            let mut removed = false;
             {
                 let mut clients = connected_clients.lock().unwrap();
                 
                 clients
                     .retain(|c| 
-                        if c.last_connection.elapsed().as_millis() > services::TIMEOUT_CONFIRM_IN_MS as u128 {
-                            warn!("removing {} cause idle since {} seconds", c.addr, services::TIMEOUT_CONFIRM_IN_MS);
-                            if let Some(rtp_udp_sink) = bin.by_name("rtpsink0") {
-                                rtp_udp_sink.emit_by_name::<()>("remove", &[&c.addr.to_string(), &5000i32]);
-                            }
-                    
-                            if let Some(rtcp_udp_sink) = bin.by_name("rtcpsink0") {
-                                rtcp_udp_sink.emit_by_name::<()>("remove", &[&c.addr.to_string(), &5001i32]);
-                            }
+                        if c.last_connection.elapsed().as_millis() > timeout_in_ms as u128 {
+                            warn!("removing {} cause idle since {} ms", c.addr, timeout_in_ms);
+                            Self::_remove_destination(&bin, &c.addr.to_string(), rtp_port);
+                            removed = true;
                             false
                         } else {
                             true
@@ -227,6 +1149,9 @@ impl RTPServer {
                 drop(clients);
             }
 
+            if removed {
+                Self::_rekey(&keyring, &key_exchange, &connected_clients, dscp);
+            }
 
             Continue(true)
         });
@@ -235,7 +1160,7 @@ impl RTPServer {
 
     
 
-    fn _prepare_bin(with_rtcp: bool, as_opus: bool) -> Result<gst::Bin, anyhow::Error> {
+    fn _prepare_bin(options: &RTPServerOptions, encryption_keyring: Option<&Arc<KeyRing>>) -> Result<gst::Bin, anyhow::Error> {
         
         // prepare by creating an empty bin
         let bin = gst::Bin::new(Some("RTPServer0"));
@@ -247,7 +1172,28 @@ impl RTPServer {
         bin.add(&queue)?;
 
         // create a payloader to handle the audio stream
-        let payloader = if as_opus {
+        let payloader = if let Some(aes67) = &options.aes67 {
+            let convert = gst::ElementFactory::make_with_name("audioconvert", None)?;
+            let resample = gst::ElementFactory::make_with_name("audioresample", None)?;
+            let capsfilter = gst::ElementFactory::make_with_name("capsfilter", None)?;
+            capsfilter.set_property("caps", gst::Caps::builder("audio/x-raw")
+                .field("rate", AES67_CLOCK_RATE)
+                .field("channels", aes67.channels)
+                .build());
+
+            let payloader = gst::ElementFactory::make_with_name("rtpL24pay", Some("pay0"))?;
+            let ptime = gst::ClockTime::from_mseconds(AES67_PTIME_MS).nseconds() as i64;
+            payloader.set_property("pt", aes67.payload_type);
+            payloader.set_property("min-ptime", ptime);
+            payloader.set_property("max-ptime", ptime);
+            // with mediaclk:direct=0 the rtp timestamps are the ptp time
+            payloader.set_property("timestamp-offset", 0u32);
+
+            bin.add_many(&[&convert, &resample, &capsfilter, &payloader])?;
+            gst::Element::link_many(&[&queue, &convert, &resample, &capsfilter, &payloader])?;
+
+            payloader
+        } else if options.as_opus {
             let opusenc = gst::ElementFactory::make_with_name("opusenc", Some("opusenc_1"))?;
             let payloader = gst::ElementFactory::make_with_name("rtpopuspay", Some("pay0"))?;
             bin.add(&opusenc)?;
@@ -259,7 +1205,7 @@ impl RTPServer {
             payloader
         } else {
             let payloader = gst::ElementFactory::make_with_name("rtpL24pay", Some("pay0"))?;
-            payloader.set_property("pt", 96u32);
+            payloader.set_property("pt", PAYLOAD_TYPE);
             bin.add(&payloader)?;
             queue.link(&payloader)?;
             
@@ -268,16 +1214,19 @@ impl RTPServer {
 
         // try it out
         if let Some(hdr_ext) = gst_rtp::RTPHeaderExtension::create_from_uri(
-            "urn:ietf:params:rtp-hdrext:ntp-64",
+            NTP64_EXTENSION_URI,
         ) {
-            hdr_ext.set_id(1);
+            hdr_ext.set_id(NTP64_EXTENSION_ID as u32);
             payloader.emit_by_name::<()>("add-extension", &[&hdr_ext]);
         } else {
             warn!("could not extend rtp header extension");
         }
 
         // send stream to a multicast group
-        let rtp_udp_sink  = Self::_set_udpsink(false, true)?;
+        let rtp_udp_sink  = Self::_set_udpsink(options.aes67.is_some(), true, &options.dscp)?;
+        if let Some(aes67) = &options.aes67 {
+            rtp_udp_sink.set_property("ttl-mc", aes67.ttl as i32);
+        }
 
         let rtpbin = gst::ElementFactory::make_with_name("rtpbin", Some("RTPBin0"))?;
 
@@ -332,20 +1281,85 @@ impl RTPServer {
         rtpbin.set_property("rtcp-sync-send-time", false);
         //rtpbin.set_property("do-retransmission", false);
 
+        if options.retransmission {
+            // NACKs are only send by the clients with the AVPF profile
+            rtpbin.set_property_from_str("rtp-profile", "avpf");
+
+            rtpbin.connect("request-aux-sender", false, |data| {
+                let sid = data[1].get::<u32>().unwrap();
+                debug!("create retransmission sender for session {}", sid);
+
+                match make_rtx_bin("rtprtxsend", "rtxsend", sid) {
+                    Ok(bin) => Some(bin.upcast::<gst::Element>().to_value()),
+                    Err(e) => {
+                        warn!("could not create retransmission sender: {:?}", e);
+                        None
+                    }
+                }
+            });
+        }
+
+        if let Some(fec) = options.fec {
+            rtpbin.connect("request-fec-encoder", false, move |data| {
+                let sid = data[1].get::<u32>().unwrap();
+                debug!("create fec encoder for session {}", sid);
+
+                let fecenc = match gst::ElementFactory::make_with_name("rtpulpfecenc", Some(&format!("fecenc{}", sid))) {
+                    Ok(fecenc) => fecenc,
+                    Err(e) => {
+                        warn!("could not create fec encoder: {:?}", e);
+                        return None
+                    }
+                };
+                fecenc.set_property("pt", FEC_PAYLOAD_TYPE);
+                fecenc.set_property("multipacket", true);
+                fecenc.set_property("percentage", fec.percentage);
+                fecenc.set_property("percentage-important", fec.percentage_important);
+
+                Some(fecenc.to_value())
+            });
+        }
+
+        // the encoders and decoders are requested with the pads
+        if let Some(keyring) = encryption_keyring {
+            debug!("encrypt the stream with srtp");
+            encryption::server_encryption(&rtpbin, keyring)?;
+        }
+
         // add rtpbin and udpsink to bin
         bin.add_many(&[&rtpbin, &rtp_udp_sink])?;
 
 
         payloader.link_pads(Some("src"), &rtpbin, Some("send_rtp_sink_0"))?;
-        rtpbin.link_pads(Some("send_rtp_src_0"), &rtp_udp_sink, Some("sink"))?; // send media stream on 5004
+        if let Some(redundancy) = &options.redundancy {
+            // identical packets over both paths
+            let tee = gst::ElementFactory::make_with_name("tee", Some("rtptee0"))?;
+            let primary_queue = gst::ElementFactory::make_with_name("queue", None)?;
+            let secondary_queue = gst::ElementFactory::make_with_name("queue", None)?;
+            let secondary_udp_sink = Self::_set_udpsink(true, true, &options.dscp)?;
+            secondary_udp_sink.set_property("name", "rtpsink1");
+            if let Some(aes67) = &options.aes67 {
+                secondary_udp_sink.set_property("ttl-mc", aes67.ttl as i32);
+            }
+
+            Self::_set_iface(&rtp_udp_sink, redundancy.primary_iface.as_deref());
+            Self::_set_iface(&secondary_udp_sink, redundancy.secondary_iface.as_deref());
+
+            bin.add_many(&[&tee, &primary_queue, &secondary_queue, &secondary_udp_sink])?;
+            rtpbin.link_pads(Some("send_rtp_src_0"), &tee, Some("sink"))?;
+            gst::Element::link_many(&[&tee, &primary_queue, &rtp_udp_sink])?;
+            gst::Element::link_many(&[&tee, &secondary_queue, &secondary_udp_sink])?;
+        } else {
+            rtpbin.link_pads(Some("send_rtp_src_0"), &rtp_udp_sink, Some("sink"))?; // send media stream on 5004
+        }
 
-        if with_rtcp {
-            let rtcp_udp_sink = Self::_set_udpsink(false, false)?;
+        if options.with_rtcp {
+            let rtcp_udp_sink = Self::_set_udpsink(options.aes67.is_some(), false, &options.dscp)?;
             bin.add(&rtcp_udp_sink)?;
             rtpbin.link_pads(Some("send_rtcp_src_0"), &rtcp_udp_sink, Some("sink"))?; // send media stream on 5004
 
-            // also link receiving part
-            let rtcp_udp_src = Self::_set_udpsrc(5002)?;
+            // also link receiving part, the NACKs for retransmission also comes in here
+            let rtcp_udp_src = Self::_set_udpsrc(options.rtp_port + 2)?;
             bin.add(&rtcp_udp_src)?;
             rtcp_udp_src.link_pads(Some("src"), &rtpbin, Some("recv_rtcp_sink_0"))?;
         }
@@ -360,7 +1374,7 @@ impl RTPServer {
 
 impl Drop for RTPServer {
     fn drop(&mut self) {
-        self.stop_sender.send(true).unwrap();
+        let _ = self.stop_sender.send(true);
         self.bin.set_state(gst::State::Null).unwrap();
     }
 }
\ No newline at end of file
@@ -0,0 +1,128 @@
+/// RTSP Server mode
+/// 
+/// serves the broadcast program on demand via RTSP, for clients behind NAT or firewalls
+/// which can not receive the pushed stream of the `RTPServer`.
+/// the program is taken from the broadcast pipeline with an `interaudiosink` and
+/// each RTSP media reads it with an `interaudiosrc` 
+use gst::prelude::*;
+use gst::glib;
+use gst_rtsp_server::prelude::*;
+use log::{debug, warn};
+
+use crate::helpers::make_element;
+
+/// Options for the RTSP Server
+#[derive(Debug, Clone)]
+pub struct RTSPServerOptions {
+    /// port or service name where the server listen for RTSP connections
+    pub service: String,
+    /// path of the stream, e.g. rtsp://server:8554/micast
+    pub mount_point: String,
+    /// encode the stream as OPUS, otherwise L24 is send
+    pub as_opus: bool,
+    /// name of the inter channel between the broadcast pipeline and the RTSP media
+    pub channel: String,
+}
+
+impl Default for RTSPServerOptions {
+    fn default() -> Self {
+        RTSPServerOptions {
+            service: "8554".to_string(),
+            mount_point: "/micast".to_string(),
+            as_opus: true,
+            channel: "micast-rtsp".to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RTSPServer {
+    bin: gst::Bin,
+    server: gst_rtsp_server::RTSPServer,
+    source_id: Option<glib::SourceId>,
+}
+
+unsafe impl Send for RTSPServer {}
+unsafe impl Sync for RTSPServer {}
+
+impl RTSPServer {
+    /// Create the RTSP Server and attach it to the default main context
+    /// 
+    /// the server supports RTP/AVP over UDP and TCP interleaved
+    pub fn new(options: RTSPServerOptions) -> Result<RTSPServer, anyhow::Error> {
+        let bin = Self::_prepare_bin(&options)?;
+
+        let server = gst_rtsp_server::RTSPServer::new();
+        server.set_service(&options.service);
+
+        let mounts = server.mount_points()
+            .ok_or_else(|| anyhow::anyhow!("rtsp server has no mount points"))?;
+
+        let factory = gst_rtsp_server::RTSPMediaFactory::new();
+        factory.set_launch(&Self::_launch_line(&options));
+        // all clients get the same media, so the program is only encoded once
+        factory.set_shared(true);
+        factory.set_protocols(gst_rtsp::RTSPLowerTrans::UDP | gst_rtsp::RTSPLowerTrans::TCP);
+
+        mounts.add_factory(&options.mount_point, factory);
+
+        let source_id = server.attach(None)?;
+        debug!("rtsp server listen on port {} with {}", options.service, options.mount_point);
+
+        Ok(RTSPServer { bin, server, source_id: Some(source_id) })
+    }
+
+    pub fn get_element(&self) -> gst::Element {
+        self.bin.clone().upcast()
+    }
+
+    /// number of currently connected RTSP clients
+    pub fn client_count(&self) -> usize {
+        // without a filter function all clients are returned
+        self.server.client_filter(None).len()
+    }
+
+    fn _launch_line(options: &RTSPServerOptions) -> String {
+        let payloader = if options.as_opus {
+            "opusenc ! rtpopuspay name=pay0 pt=96"
+        } else {
+            "rtpL24pay name=pay0 pt=96"
+        };
+
+        format!(
+            "( interaudiosrc channel={} ! audioconvert ! audioresample ! {} )", 
+            options.channel, 
+            payloader
+        )
+    }
+
+    /// bin for the broadcast pipeline, which hand over the program to the RTSP media
+    fn _prepare_bin(options: &RTSPServerOptions) -> Result<gst::Bin, anyhow::Error> {
+        let bin = gst::Bin::new(Some("RTSPServer0"));
+
+        let queue = make_element("queue", None)?;
+        queue.set_property("flush-on-eos", true);
+
+        let intersink = make_element("interaudiosink", None)?;
+        intersink.set_property("channel", &options.channel);
+
+        bin.add_many(&[&queue, &intersink])?;
+        queue.link(&intersink)?;
+
+        let ghost_pad = gst::GhostPad::with_target(Some("sink"), &queue.static_pad("sink").unwrap())?;
+        bin.add_pad(&ghost_pad)?;
+
+        Ok(bin)
+    }
+}
+
+impl Drop for RTSPServer {
+    fn drop(&mut self) {
+        if let Some(source_id) = self.source_id.take() {
+            source_id.remove();
+        }
+        if let Err(e) = self.bin.set_state(gst::State::Null) {
+            warn!("could not stop rtsp server bin: {:?}", e);
+        }
+    }
+}
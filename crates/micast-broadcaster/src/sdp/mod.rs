@@ -0,0 +1,351 @@
+/// Minimal SDP (RFC 4566) description of the RTP stream
+/// 
+/// used to describe the stream to other players than the `PlaybackClient`,
+/// e.g. VLC, ffplay or gst-launch
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+/// a payload format of the media line (`a=rtpmap` and `a=fmtp`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaFormat {
+    pub payload_type: u32,
+    pub encoding_name: String,
+    pub clock_rate: u32,
+    pub channels: Option<u32>,
+    pub fmtp: Option<String>,
+}
+
+impl MediaFormat {
+    pub fn new(payload_type: u32, encoding_name: &str, clock_rate: u32, channels: Option<u32>) -> Self {
+        MediaFormat {
+            payload_type,
+            encoding_name: encoding_name.to_string(),
+            clock_rate,
+            channels,
+            fmtp: None,
+        }
+    }
+
+    pub fn with_fmtp(mut self, fmtp: &str) -> Self {
+        self.fmtp = Some(fmtp.to_string());
+        self
+    }
+
+    /// RTP caps of the payload format, e.g. for the `request-pt-map` of a rtpbin
+    pub(crate) fn caps(&self) -> gst::Caps {
+        let mut caps = gst::Caps::builder("application/x-rtp")
+            .field("media", "audio")
+            .field("payload", self.payload_type as i32)
+            .field("clock-rate", self.clock_rate as i32)
+            .field("encoding-name", self.encoding_name.to_uppercase());
+
+        if let Some(channels) = self.channels {
+            caps = caps.field("channels", channels as i32);
+        }
+        if let Some(fmtp) = &self.fmtp {
+            // fmtp parameters are fields of the caps, e.g. `sprop-stereo=1`
+            for (key, value) in fmtp.split(';').filter_map(|parameter| parameter.trim().split_once('=')) {
+                caps = caps.field(key.trim(), value.trim());
+            }
+        }
+
+        caps.build()
+    }
+}
+
+/// Session description with exactly one audio media
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionDescription {
+    /// session id for the origin line, normaly the creation time
+    pub session_id: u64,
+    /// address of the server which sends the stream
+    pub origin_address: String,
+    /// `s=` line
+    pub session_name: String,
+    /// destination address of the stream, unicast or multicast group
+    pub connection_address: String,
+    /// ttl for multicast connection addresses
+    pub ttl: Option<u32>,
+    /// RTP port, RTCP is expected on `port + 1` 
+    pub port: u32,
+    /// port where the sender receives RTCP (`a=rtcp`), `None` for `port + 1`
+    pub rtcp_port: Option<u32>,
+    /// payload formats, the first one is the audio stream 
+    pub formats: Vec<MediaFormat>,
+    /// RTP header extensions as (id, uri)
+    pub extensions: Vec<(u8, String)>,
+    /// value of the `a=crypto` attribute if the stream is encrypted with SRTP
+    pub crypto: Option<String>,
+    /// additional media attributes without the `a=`
+    pub attributes: Vec<String>,
+}
+
+impl SessionDescription {
+    /// the stream is encrypted with SRTP
+    pub fn is_encrypted(&self) -> bool {
+        self.crypto.is_some()
+    }
+
+    /// value of the first additional attribute with the given name, e.g. `ts-refclk`
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find_map(|attribute| {
+            match attribute.split_once(':') {
+                Some((key, value)) if key == name => Some(value),
+                None if attribute == name => Some(""),
+                _ => None,
+            }
+        })
+    }
+}
+
+impl FromStr for SessionDescription {
+    type Err = anyhow::Error;
+
+    /// parse a SDP with one audio media, as generated by [`SessionDescription`]
+    /// and by other RTP senders like AES67 devices
+    fn from_str(sdp: &str) -> Result<Self, Self::Err> {
+        let mut description = SessionDescription {
+            session_id: 0,
+            origin_address: String::new(),
+            session_name: String::new(),
+            connection_address: String::new(),
+            ttl: None,
+            port: 0,
+            rtcp_port: None,
+            formats: Vec::new(),
+            extensions: Vec::new(),
+            crypto: None,
+            attributes: Vec::new(),
+        };
+        let mut has_media = false;
+
+        for line in sdp.lines() {
+            let (key, value) = match line.split_once('=') {
+                Some(kv) => kv,
+                None => continue,
+            };
+
+            match key {
+                "o" => {
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    if fields.len() < 6 {
+                        return Err(anyhow!("invalid origin line: {}", line));
+                    }
+                    description.session_id = fields[1].parse().unwrap_or(0);
+                    description.origin_address = fields[5].to_string();
+                },
+                "s" => description.session_name = value.to_string(),
+                "c" => {
+                    let address = value.split_whitespace().nth(2)
+                        .ok_or_else(|| anyhow!("invalid connection line: {}", line))?;
+                    let mut parts = address.split('/');
+                    description.connection_address = parts.next().unwrap_or_default().to_string();
+                    description.ttl = parts.next().and_then(|ttl| ttl.parse().ok());
+                },
+                "m" => {
+                    // only the first media is used
+                    if has_media {
+                        break;
+                    }
+                    let fields: Vec<&str> = value.split_whitespace().collect();
+                    if fields.len() < 4 || fields[0] != "audio" {
+                        return Err(anyhow!("unsupported media line: {}", line));
+                    }
+                    description.port = fields[1].parse()?;
+                    for payload_type in &fields[3..] {
+                        description.formats.push(MediaFormat::new(payload_type.parse()?, "", 0, None));
+                    }
+                    has_media = true;
+                },
+                "a" => {
+                    let (name, attribute_value) = value.split_once(':').unwrap_or((value, ""));
+                    match name {
+                        "rtpmap" => {
+                            let (payload_type, encoding) = attribute_value.split_once(' ')
+                                .ok_or_else(|| anyhow!("invalid rtpmap: {}", line))?;
+                            let payload_type: u32 = payload_type.parse()?;
+                            let mut encoding = encoding.split('/');
+                            let encoding_name = encoding.next().unwrap_or_default();
+                            let clock_rate = encoding.next().and_then(|rate| rate.parse().ok()).unwrap_or(0);
+                            let channels = encoding.next().and_then(|channels| channels.parse().ok());
+
+                            match description.formats.iter_mut().find(|format| format.payload_type == payload_type) {
+                                Some(format) => {
+                                    format.encoding_name = encoding_name.to_string();
+                                    format.clock_rate = clock_rate;
+                                    format.channels = channels;
+                                },
+                                None => description.formats.push(MediaFormat::new(payload_type, encoding_name, clock_rate, channels)),
+                            }
+                        },
+                        "fmtp" => {
+                            if let Some((payload_type, fmtp)) = attribute_value.split_once(' ') {
+                                let payload_type: u32 = payload_type.parse()?;
+                                if let Some(format) = description.formats.iter_mut().find(|format| format.payload_type == payload_type) {
+                                    format.fmtp = Some(fmtp.to_string());
+                                }
+                            }
+                        },
+                        "extmap" => {
+                            if let Some((id, uri)) = attribute_value.split_once(' ') {
+                                // the id may have a direction like 1/sendonly
+                                let id = id.split('/').next().unwrap_or_default().parse()?;
+                                description.extensions.push((id, uri.to_string()));
+                            }
+                        },
+                        "crypto" => description.crypto = Some(attribute_value.to_string()),
+                        // the address after the port is ignored
+                        "rtcp" => description.rtcp_port = attribute_value.split_whitespace().next().and_then(|port| port.parse().ok()),
+                        // these are generated on output
+                        "sendonly" | "recvonly" | "sendrecv" => (),
+                        _ => description.attributes.push(value.to_string()),
+                    }
+                },
+                _ => (),
+            }
+        }
+
+        if !has_media {
+            return Err(anyhow!("sdp without audio media"));
+        }
+        if description.rtcp_port == Some(description.port + 1) {
+            description.rtcp_port = None;
+        }
+
+        Ok(description)
+    }
+}
+
+impl fmt::Display for SessionDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let profile = if self.is_encrypted() { "RTP/SAVP" } else { "RTP/AVP" };
+        let payload_types = self.formats.iter()
+            .map(|format| format.payload_type.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        write!(f, "v=0\r\n")?;
+        write!(f, "o=- {} {} IN IP4 {}\r\n", self.session_id, self.session_id, self.origin_address)?;
+        write!(f, "s={}\r\n", self.session_name)?;
+        match self.ttl {
+            Some(ttl) => write!(f, "c=IN IP4 {}/{}\r\n", self.connection_address, ttl)?,
+            None => write!(f, "c=IN IP4 {}\r\n", self.connection_address)?,
+        }
+        write!(f, "t=0 0\r\n")?;
+        write!(f, "m=audio {} {} {}\r\n", self.port, profile, payload_types)?;
+        write!(f, "a=rtcp:{}\r\n", self.rtcp_port.unwrap_or(self.port + 1))?;
+
+        for format in &self.formats {
+            match format.channels {
+                Some(channels) => write!(f, "a=rtpmap:{} {}/{}/{}\r\n", format.payload_type, format.encoding_name, format.clock_rate, channels)?,
+                None => write!(f, "a=rtpmap:{} {}/{}\r\n", format.payload_type, format.encoding_name, format.clock_rate)?,
+            }
+            if let Some(fmtp) = &format.fmtp {
+                write!(f, "a=fmtp:{} {}\r\n", format.payload_type, fmtp)?;
+            }
+        }
+
+        for (id, uri) in &self.extensions {
+            write!(f, "a=extmap:{} {}\r\n", id, uri)?;
+        }
+
+        if let Some(crypto) = &self.crypto {
+            write!(f, "a=crypto:{}\r\n", crypto)?;
+        }
+
+        for attribute in &self.attributes {
+            write!(f, "a={}\r\n", attribute)?;
+        }
+
+        write!(f, "a=sendonly\r\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn description() -> SessionDescription {
+        SessionDescription {
+            session_id: 1700000000,
+            origin_address: "192.168.1.2".to_string(),
+            session_name: "micast-dj".to_string(),
+            connection_address: "239.69.1.1".to_string(),
+            ttl: Some(32),
+            port: 5004,
+            rtcp_port: None,
+            formats: vec![
+                MediaFormat::new(96, "L24", 48000, Some(2)),
+                MediaFormat::new(97, "rtx", 48000, None).with_fmtp("apt=96"),
+            ],
+            extensions: vec![(1, "urn:ietf:params:rtp-hdrext:ntp-64".to_string())],
+            crypto: None,
+            attributes: vec!["ptime:1".to_string(), "ts-refclk:ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0".to_string()],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let description = description();
+        let sdp = description.to_string();
+        assert!(sdp.contains("m=audio 5004 RTP/AVP 96 97\r\n"));
+        assert!(sdp.contains("a=rtcp:5005\r\n"));
+        assert_eq!(sdp.parse::<SessionDescription>().unwrap(), description);
+
+        let other_rtcp_port = SessionDescription { rtcp_port: Some(5006), ..description.clone() };
+        let sdp = other_rtcp_port.to_string();
+        assert!(sdp.contains("a=rtcp:5006\r\n"));
+        assert_eq!(sdp.parse::<SessionDescription>().unwrap(), other_rtcp_port);
+
+        let mut encrypted = description;
+        encrypted.crypto = Some("1 AES_CM_128_HMAC_SHA1_80 inline:key".to_string());
+        let sdp = encrypted.to_string();
+        assert!(sdp.contains("RTP/SAVP"));
+        assert_eq!(sdp.parse::<SessionDescription>().unwrap(), encrypted);
+    }
+
+    #[test]
+    fn attributes() {
+        let description = description();
+        assert_eq!(description.attribute("ptime"), Some("1"));
+        assert_eq!(description.attribute("ts-refclk"), Some("ptp=IEEE1588-2008:00-1D-C1-FF-FE-12-34-56:0"));
+        assert_eq!(description.attribute("mediaclk"), None);
+        assert!(!description.is_encrypted());
+    }
+
+    #[test]
+    fn sdp_of_other_senders() {
+        let sdp = "v=0\n\
+            o=- 4711 0 IN IP4 10.0.0.5\n\
+            s=Stage Left\n\
+            c=IN IP4 239.1.2.3/16\n\
+            t=0 0\n\
+            m=audio 5008 RTP/AVP 98\n\
+            a=rtpmap:98 L24/48000/8\n\
+            a=extmap:2/sendonly urn:example\n\
+            a=recvonly\n\
+            a=mediaclk:direct=0\n\
+            m=video 5010 RTP/AVP 99\n\
+            a=rtpmap:99 H264/90000\n";
+
+        let description = sdp.parse::<SessionDescription>().unwrap();
+        assert_eq!(description.session_id, 4711);
+        assert_eq!(description.session_name, "Stage Left");
+        assert_eq!(description.connection_address, "239.1.2.3");
+        assert_eq!(description.ttl, Some(16));
+        assert_eq!(description.port, 5008);
+        assert_eq!(description.formats, vec![MediaFormat::new(98, "L24", 48000, Some(8))]);
+        assert_eq!(description.extensions, vec![(2, "urn:example".to_string())]);
+        assert_eq!(description.attributes, vec!["mediaclk:direct=0".to_string()]);
+    }
+
+    #[test]
+    fn invalid_sdp() {
+        assert!("v=0\r\ns=no media\r\n".parse::<SessionDescription>().is_err());
+        assert!("v=0\r\nm=video 5004 RTP/AVP 96\r\n".parse::<SessionDescription>().is_err());
+        assert!("v=0\r\no=- 1\r\nm=audio 5004 RTP/AVP 96\r\n".parse::<SessionDescription>().is_err());
+        assert!("v=0\r\nm=audio port RTP/AVP 96\r\n".parse::<SessionDescription>().is_err());
+    }
+}
@@ -0,0 +1,339 @@
+/// Credentials of the clients for the admission by the `RTPServer`
+///
+/// a client proves in every confirmation that it knows its token, without sending the token:
+/// `mirror|cid=<token id>|cts=<unix ms>|cnonce=<hex>|proof=<hex>|`. the proof is a HMAC-SHA256
+/// keyed by the token over the confirmation up to the proof. the server looks up the token by its id,
+/// rejects old or replayed proofs and admits only clients whose token is in its [`TokenStore`].
+///
+/// certificate fingerprints are not supported as credential: the confirmations are plain UDP without a
+/// TLS handshake, so a client can not prove that it holds the private key of a certificate. a fingerprint
+/// is no secret and anyone who sees a confirmation could present it, a token is proven without being sent
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Instant, SystemTime};
+
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use sha2::{Digest, Sha256};
+
+use super::auth;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// length of the token id in bytes
+const ID_LENGTH: usize = 8;
+
+static SEEN_NONCES: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A token of a client, written as `token:<token>`
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Credential {
+    Token(String),
+}
+
+impl Credential {
+    /// public id of the credential, send in the confirmations instead of the token
+    pub fn id(&self) -> String {
+        match self {
+            Credential::Token(token) => hex::encode(&Sha256::digest(token.as_bytes())[..ID_LENGTH]),
+        }
+    }
+
+    fn mac(&self, data: &str) -> HmacSha256 {
+        let Credential::Token(token) = self;
+        let mut mac = HmacSha256::new_from_slice(token.as_bytes()).expect("hmac accepts keys of any length");
+        mac.update(data.as_bytes());
+        mac
+    }
+
+    /// the fields proving the credential, appended to the message
+    ///
+    /// # Arguments
+    /// * `message` - the message up to the credential, it is covered by the proof
+    pub(crate) fn prove(&self, message: &str) -> String {
+        let fields = format!("cid={}|cts={}|cnonce={}|", self.id(), auth::unix_ms(), auth::nonce());
+        let proof = self.mac(&format!("{}{}", message, fields)).finalize().into_bytes();
+        format!("{}proof={}|", fields, hex::encode(proof))
+    }
+}
+
+// never print a token
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Token({})", self.id())
+    }
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Token(token) => write!(f, "token:{}", token),
+        }
+    }
+}
+
+impl FromStr for Credential {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("token", token)) if !token.is_empty() => Ok(Credential::Token(token.to_string())),
+            _ => Err(anyhow!("credential needs the form token:<token>")),
+        }
+    }
+}
+
+/// The proof of a credential in a confirmation, see [`Credential::prove`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CredentialProof {
+    /// id of the credential
+    pub id: String,
+    ts: u64,
+    nonce: String,
+    proof: Vec<u8>,
+    /// the message up to the proof
+    signed: String,
+}
+
+impl CredentialProof {
+    /// the proof in a message, `None` if the message has none
+    pub(crate) fn of(message: &str) -> Option<CredentialProof> {
+        let message = message.trim_end_matches(['\n', '\r']);
+        let proof_position = message.rfind("|proof=")?;
+        let signed = &message[..proof_position + 1];
+        let field = |key: &str| signed.split('|').find_map(|field| field.strip_prefix(key).and_then(|field| field.strip_prefix('=')));
+
+        Some(CredentialProof {
+            id: field("cid")?.to_string(),
+            ts: field("cts")?.parse().ok()?,
+            nonce: field("cnonce")?.to_string(),
+            proof: hex::decode(message[proof_position + 7..].split('|').next()?).ok()?,
+            signed: signed.to_string(),
+        })
+    }
+
+    /// the proof was made with the credential, is not older than [`auth::REPLAY_WINDOW`] and not replayed
+    pub(crate) fn verify(&self, credential: &Credential) -> Result<()> {
+        if credential.id() != self.id {
+            return Err(anyhow!("proof of another credential"));
+        }
+        credential.mac(&self.signed).verify_slice(&self.proof).map_err(|_| anyhow!("invalid proof"))?;
+
+        if auth::unix_ms().abs_diff(self.ts) > auth::REPLAY_WINDOW.as_millis() as u64 {
+            return Err(anyhow!("proof is too old"));
+        }
+
+        let mut seen_nonces = SEEN_NONCES.lock();
+        // nonces older than the window are rejected by the timestamp already
+        seen_nonces.retain(|_, seen| seen.elapsed() < auth::REPLAY_WINDOW * 2);
+        if seen_nonces.insert(format!("{}{}", self.id, self.nonce), Instant::now()).is_some() {
+            return Err(anyhow!("replayed proof"));
+        }
+        Ok(())
+    }
+}
+
+/// The credentials of the clients the `RTPServer` admits
+pub trait TokenStore: Send + Sync + fmt::Debug {
+    /// the credential with the id and the name of its client, `None` if the id is unknown
+    fn lookup(&self, id: &str) -> Result<Option<(Credential, String)>>;
+}
+
+/// Credentials in memory
+#[derive(Clone, Default)]
+pub struct TokenList(HashMap<String, (Credential, String)>);
+
+impl TokenList {
+    pub fn new() -> Self {
+        TokenList::default()
+    }
+
+    /// admit the client with the credential
+    ///
+    /// # Arguments
+    /// * `credential` - token of the client
+    /// * `name` - name of the client, e.g. its room
+    pub fn insert(&mut self, credential: Credential, name: &str) {
+        self.0.insert(credential.id(), (credential, name.to_string()));
+    }
+
+    /// revoke the credential
+    pub fn remove(&mut self, credential: &Credential) {
+        self.0.remove(&credential.id());
+    }
+}
+
+// never print the tokens
+impl fmt::Debug for TokenList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TokenList({} credentials)", self.0.len())
+    }
+}
+
+impl TokenStore for TokenList {
+    fn lookup(&self, id: &str) -> Result<Option<(Credential, String)>> {
+        Ok(self.0.get(id).cloned())
+    }
+}
+
+/// Credentials in a file, one `<credential> <name>` per line, empty lines and lines starting with `#` are ignored
+///
+/// the file is read again when it changed, revoked credentials are rejected with the next confirmation
+#[derive(Debug)]
+pub struct TokenFile {
+    path: PathBuf,
+    /// the credentials of the file with its modification time and length
+    cache: Mutex<Option<((SystemTime, u64), TokenList)>>,
+}
+
+impl TokenFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        TokenFile {
+            path: path.into(),
+            cache: Mutex::new(None),
+        }
+    }
+
+    /// parse the credentials of the file
+    fn read(&self) -> Result<TokenList> {
+        let content = std::fs::read_to_string(&self.path)
+            .map_err(|e| anyhow!("could not read token file {}: {}", self.path.display(), e))?;
+
+        let mut list = TokenList::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (entry, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let entry = entry.parse::<Credential>()
+                .map_err(|e| anyhow!("invalid credential in line {} of {}: {}", number + 1, self.path.display(), e))?;
+            list.insert(entry, name.trim());
+        }
+        Ok(list)
+    }
+}
+
+impl TokenStore for TokenFile {
+    fn lookup(&self, id: &str) -> Result<Option<(Credential, String)>> {
+        let metadata = std::fs::metadata(&self.path)
+            .map_err(|e| anyhow!("could not read token file {}: {}", self.path.display(), e))?;
+        let version = (metadata.modified()?, metadata.len());
+
+        let mut cache = self.cache.lock();
+        match cache.as_ref() {
+            Some((cached_version, list)) if *cached_version == version => list.lookup(id),
+            _ => {
+                let list = self.read()?;
+                let entry = list.lookup(id);
+                *cache = Some((version, list));
+                entry
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(token: &str) -> Credential {
+        Credential::Token(token.to_string())
+    }
+
+    /// a proof with the timestamp
+    fn proof_at(credential: &Credential, ts: u64) -> CredentialProof {
+        let message = format!("mirror|cid={}|cts={}|cnonce={}|", credential.id(), ts, auth::nonce());
+        let proof = credential.mac(&message).finalize().into_bytes();
+        CredentialProof::of(&format!("{}proof={}|", message, hex::encode(proof))).unwrap()
+    }
+
+    #[test]
+    fn credential_round_trip() {
+        let credential = "token:a|b=c:d".parse::<Credential>().unwrap();
+        assert_eq!(credential, token("a|b=c:d"));
+        assert_eq!(credential.to_string().parse::<Credential>().unwrap(), credential);
+
+        assert!("token:".parse::<Credential>().is_err());
+        assert!("secret".parse::<Credential>().is_err());
+        assert!("sha256:00ff".parse::<Credential>().is_err());
+    }
+
+    #[test]
+    fn token_is_never_printed() {
+        let credential = token("secret-token");
+        assert_eq!(credential.id().len(), ID_LENGTH * 2);
+        assert!(!format!("{:?}", credential).contains("secret-token"));
+        assert!(!credential.prove("mirror|").contains("secret-token"));
+    }
+
+    #[test]
+    fn proof_of_the_credential() {
+        let credential = token("kitchen");
+        let message = format!("mirror|ctl=5890|{}\n", credential.prove("mirror|ctl=5890|"));
+        let proof = CredentialProof::of(&message).unwrap();
+        assert_eq!(proof.id, credential.id());
+
+        assert!(proof.verify(&token("bath")).is_err());
+        assert!(proof.verify(&credential).is_ok());
+        // replayed
+        assert!(proof.verify(&credential).is_err());
+
+        let tampered = message.replace("ctl=5890", "ctl=5891");
+        assert!(CredentialProof::of(&tampered).unwrap().verify(&credential).is_err());
+
+        assert_eq!(CredentialProof::of("mirror|ctl=5890|"), None);
+        assert_eq!(CredentialProof::of("mirror|cid=00|cts=now|cnonce=00|proof=00|"), None);
+    }
+
+    #[test]
+    fn old_proofs() {
+        let credential = token("kitchen");
+        let window = auth::REPLAY_WINDOW.as_millis() as u64;
+        let now = auth::unix_ms();
+
+        assert!(proof_at(&credential, now - window / 2).verify(&credential).is_ok());
+        assert!(proof_at(&credential, now - window - 1000).verify(&credential).is_err());
+        assert!(proof_at(&credential, now + window + 1000).verify(&credential).is_err());
+    }
+
+    #[test]
+    fn token_list() {
+        let mut list = TokenList::new();
+        list.insert(token("kitchen"), "Kitchen");
+        assert_eq!(list.lookup(&token("kitchen").id()).unwrap(), Some((token("kitchen"), "Kitchen".to_string())));
+        assert_eq!(list.lookup(&token("bath").id()).unwrap(), None);
+
+        list.remove(&token("kitchen"));
+        assert_eq!(list.lookup(&token("kitchen").id()).unwrap(), None);
+        assert!(!format!("{:?}", list).contains("kitchen"));
+    }
+
+    #[test]
+    fn token_file() {
+        let path = std::env::temp_dir().join(format!("micast-tokens-{}", auth::nonce()));
+        std::fs::write(&path, "# clients\n\ntoken:kitchen Kitchen and Dining\n  token:bath\n").unwrap();
+
+        let file = TokenFile::new(&path);
+        assert_eq!(file.lookup(&token("kitchen").id()).unwrap(), Some((token("kitchen"), "Kitchen and Dining".to_string())));
+        assert_eq!(file.lookup(&token("bath").id()).unwrap(), Some((token("bath"), String::new())));
+        assert_eq!(file.lookup(&token("garage").id()).unwrap(), None);
+
+        // the file is read again after a change, revoked credentials are unknown
+        std::fs::write(&path, "token:garage\n").unwrap();
+        assert_eq!(file.lookup(&token("kitchen").id()).unwrap(), None);
+        assert!(file.lookup(&token("garage").id()).unwrap().is_some());
+
+        std::fs::write(&path, "token:garage\nkitchen\n").unwrap();
+        let error = file.lookup(&token("garage").id()).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(file.lookup(&token("garage").id()).is_err());
+    }
+}
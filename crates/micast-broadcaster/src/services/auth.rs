@@ -0,0 +1,260 @@
+/// HMAC signing of the discovery and confirmation messages
+///
+/// with a shared deployment secret every message gets a timestamp, a nonce and a
+/// HMAC-SHA256 signature appended: `<message>|ts=<unix ms>|nonce=<hex>|sig=<hex>|`.
+/// unsigned, invalid or replayed messages are rejected and counted.
+/// without a secret messages are send unsigned and every message is accepted
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// maximum age of a message, and maximum clock difference between server and client
+pub const REPLAY_WINDOW: Duration = Duration::from_secs(30);
+
+static SHARED_SECRET: Lazy<RwLock<Option<Vec<u8>>>> = Lazy::new(|| RwLock::new(None));
+static SEEN_NONCES: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static REJECTED_UNSIGNED: AtomicU64 = AtomicU64::new(0);
+static REJECTED_INVALID: AtomicU64 = AtomicU64::new(0);
+static REJECTED_REPLAYED: AtomicU64 = AtomicU64::new(0);
+
+/// set the shared secret of the deployment, has to be the same on the server and all clients.
+/// should be set before the `Broadcast` or the `PlaybackClient` is created
+pub fn set_shared_secret(secret: Option<&[u8]>) {
+    *SHARED_SECRET.write() = secret.map(|secret| secret.to_vec());
+}
+
+/// messages are signed and verified
+pub fn is_enabled() -> bool {
+    SHARED_SECRET.read().is_some()
+}
+
+/// Why a message was rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthError {
+    /// the message has no signature
+    Unsigned,
+    /// the signature or the auth fields are wrong
+    Invalid,
+    /// the message is too old or the nonce was already seen
+    Replayed,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Unsigned => write!(f, "unsigned message"),
+            AuthError::Invalid => write!(f, "invalid signature"),
+            AuthError::Replayed => write!(f, "replayed message"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+/// Counters of the rejected messages
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AuthStats {
+    pub unsigned: u64,
+    pub invalid: u64,
+    pub replayed: u64,
+}
+
+/// counters of the rejected messages since the start
+pub fn auth_stats() -> AuthStats {
+    AuthStats {
+        unsigned: REJECTED_UNSIGNED.load(Ordering::Relaxed),
+        invalid: REJECTED_INVALID.load(Ordering::Relaxed),
+        replayed: REJECTED_REPLAYED.load(Ordering::Relaxed),
+    }
+}
+
+pub(crate) fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// 16 random bytes as hex, unique per message and not predictable by other hosts
+pub(crate) fn nonce() -> String {
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce).expect("the os provides random numbers");
+    hex::encode(nonce)
+}
+
+fn mac(secret: &[u8], data: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac accepts keys of any length");
+    mac.update(data.as_bytes());
+    mac
+}
+
+/// key derived from the shared secret for one purpose, `None` without a secret
+pub(crate) fn derive_key(purpose: &str) -> Option<[u8; 32]> {
+    let secret = SHARED_SECRET.read();
+    let secret = secret.as_ref()?;
+    Some(mac(secret, purpose).finalize().into_bytes().into())
+}
+
+/// sign the message with the shared secret, returns the message unchanged without a secret
+pub fn sign(message: &str) -> String {
+    let secret = SHARED_SECRET.read();
+    match secret.as_ref() {
+        Some(secret) => sign_with(secret, message),
+        None => message.to_string(),
+    }
+}
+
+pub(crate) fn sign_with(secret: &[u8], message: &str) -> String {
+    let mut signed = message.trim_end_matches(['\n', '\r']).to_string();
+    if !signed.ends_with('|') {
+        signed.push('|');
+    }
+    signed.push_str(&format!("ts={}|nonce={}|", unix_ms(), nonce()));
+
+    let signature = hex::encode(mac(secret, &signed).finalize().into_bytes());
+    format!("{}sig={}|\n", signed, signature)
+}
+
+/// verify the signature, age and nonce of the message
+///
+/// # Returns
+/// the message without the auth fields, or the message unchanged without a secret
+pub fn verify(message: &str) -> Result<String, AuthError> {
+    verify_counted(message, true)
+}
+
+/// verify the signature and age of a record that is repeated unchanged, like the mDNS TXT records
+///
+/// the nonce is not checked, replays are limited by the [`REPLAY_WINDOW`] only
+pub(crate) fn verify_record(record: &str) -> Result<String, AuthError> {
+    verify_counted(record, false)
+}
+
+fn verify_counted(message: &str, check_nonce: bool) -> Result<String, AuthError> {
+    let secret = SHARED_SECRET.read();
+    let secret = match secret.as_ref() {
+        Some(secret) => secret,
+        None => return Ok(message.to_string()),
+    };
+
+    let result = verify_with(secret, message.trim_end_matches(['\n', '\r']), check_nonce);
+    match result {
+        Err(AuthError::Unsigned) => REJECTED_UNSIGNED.fetch_add(1, Ordering::Relaxed),
+        Err(AuthError::Invalid) => REJECTED_INVALID.fetch_add(1, Ordering::Relaxed),
+        Err(AuthError::Replayed) => REJECTED_REPLAYED.fetch_add(1, Ordering::Relaxed),
+        Ok(_) => 0,
+    };
+    result
+}
+
+pub(crate) fn verify_with(secret: &[u8], message: &str, check_nonce: bool) -> Result<String, AuthError> {
+    let sig_position = message.rfind("|sig=").ok_or(AuthError::Unsigned)?;
+    let signed = &message[..sig_position + 1];
+    let signature = hex::decode(message[sig_position + 5..].trim_end_matches('|')).map_err(|_| AuthError::Invalid)?;
+
+    mac(secret, signed).verify_slice(&signature).map_err(|_| AuthError::Invalid)?;
+
+    // the signature is valid, so the auth fields are the ones we appended
+    let ts_position = signed.rfind("|ts=").ok_or(AuthError::Invalid)?;
+    let mut ts = None;
+    let mut nonce = None;
+    for field in signed[ts_position + 1..].split('|') {
+        match field.split_once('=') {
+            Some(("ts", value)) => ts = value.parse::<u64>().ok(),
+            Some(("nonce", value)) => nonce = Some(value),
+            _ => {}
+        }
+    }
+    let (ts, nonce) = ts.zip(nonce).ok_or(AuthError::Invalid)?;
+
+    if unix_ms().abs_diff(ts) > REPLAY_WINDOW.as_millis() as u64 {
+        return Err(AuthError::Replayed);
+    }
+    if !check_nonce {
+        return Ok(format!("{}\n", &signed[..ts_position + 1]));
+    }
+
+    let mut seen_nonces = SEEN_NONCES.lock();
+    // nonces older than the window are rejected by the timestamp already
+    seen_nonces.retain(|_, seen| seen.elapsed() < REPLAY_WINDOW * 2);
+    if seen_nonces.insert(nonce.to_string(), Instant::now()).is_some() {
+        return Err(AuthError::Replayed);
+    }
+
+    Ok(format!("{}\n", &signed[..ts_position + 1]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"deployment secret";
+
+    /// the message signed with the timestamp
+    fn signed_at(message: &str, ts: u64) -> String {
+        let signed = format!("{}ts={}|nonce={}|", message, ts, nonce());
+        format!("{}sig={}|", signed, hex::encode(mac(SECRET, &signed).finalize().into_bytes()))
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let signed = sign_with(SECRET, "micast-dj|v=1|rtp=5004|\n");
+        assert!(signed.starts_with("micast-dj|v=1|rtp=5004|ts="));
+        assert!(signed.ends_with("|\n"));
+        assert_eq!(verify_with(SECRET, signed.trim_end(), true), Ok("micast-dj|v=1|rtp=5004|\n".to_string()));
+
+        // a separator is added to messages without
+        let signed = sign_with(SECRET, "mirror");
+        assert_eq!(verify_with(SECRET, signed.trim_end(), true), Ok("mirror|\n".to_string()));
+    }
+
+    #[test]
+    fn invalid_messages() {
+        let signed = sign_with(SECRET, "micast-dj|v=1|rtp=5004|");
+        let signed = signed.trim_end();
+
+        assert_eq!(verify_with(SECRET, "micast-dj|v=1|rtp=5004|", true), Err(AuthError::Unsigned));
+        assert_eq!(verify_with(b"other secret", signed, true), Err(AuthError::Invalid));
+        assert_eq!(verify_with(SECRET, &signed.replace("rtp=5004", "rtp=5006"), true), Err(AuthError::Invalid));
+        let last = if signed.ends_with("0|") { "1|" } else { "0|" };
+        assert_eq!(verify_with(SECRET, &format!("{}{}", &signed[..signed.len() - 2], last), true), Err(AuthError::Invalid));
+        assert_eq!(verify_with(SECRET, &signed.replace("|sig=", "|sig=zz"), true), Err(AuthError::Invalid));
+    }
+
+    #[test]
+    fn replayed_messages() {
+        let signed = sign_with(SECRET, "mirror|");
+        let signed = signed.trim_end();
+        assert!(verify_with(SECRET, signed, true).is_ok());
+        assert_eq!(verify_with(SECRET, signed, true), Err(AuthError::Replayed));
+
+        // repeated records are only checked by their age
+        let record = sign_with(SECRET, "v=1|rtp=5004|");
+        assert!(verify_with(SECRET, record.trim_end(), false).is_ok());
+        assert!(verify_with(SECRET, record.trim_end(), false).is_ok());
+    }
+
+    #[test]
+    fn clock_skew() {
+        let window = REPLAY_WINDOW.as_millis() as u64;
+        let now = unix_ms();
+
+        assert!(verify_with(SECRET, &signed_at("mirror|", now - window / 2), true).is_ok());
+        assert!(verify_with(SECRET, &signed_at("mirror|", now + window / 2), true).is_ok());
+        assert_eq!(verify_with(SECRET, &signed_at("mirror|", now - window - 1000), true), Err(AuthError::Replayed));
+        assert_eq!(verify_with(SECRET, &signed_at("mirror|", now + window + 1000), false), Err(AuthError::Replayed));
+    }
+
+    #[test]
+    fn nonces_are_unique() {
+        let nonces: std::collections::HashSet<String> = (0..1000).map(|_| nonce()).collect();
+        assert_eq!(nonces.len(), 1000);
+        assert!(nonces.iter().all(|nonce| nonce.len() == 32 && hex::decode(nonce).is_ok()));
+    }
+}
@@ -0,0 +1,381 @@
+/// Remote control channel from the server to the clients
+///
+/// the server sends a command to the control port of a client and waits for the acknowledgement.
+/// commands are resend until they are acknowledged, the client executes every command id only once.
+///
+/// `micast-ctl|id=<id>|cmd=<command>|<arguments>|` is answered with `micast-ack|id=<id>|ok=1|`
+/// or `micast-ack|id=<id>|ok=0|error=<error>|`, all messages are signed if a shared secret is set
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use once_cell::sync::Lazy;
+use log::{debug, info, trace, warn};
+
+use crate::qos;
+use super::auth;
+use super::discovery::{escape, unescape};
+
+/// port where the clients listen for commands
+pub const CONTROL_PORT: u16 = 5886;
+
+const CONTROL_MAGIC: &str = "micast-ctl";
+const ACK_MAGIC: &str = "micast-ack";
+const RETRIES: u32 = 3;
+
+// starts with the time, so a restarted server does not repeat the ids of the last run
+static NEXT_COMMAND_ID: Lazy<AtomicU32> = Lazy::new(|| {
+    AtomicU32::new(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as u32).unwrap_or(1))
+});
+
+/// Commands the server can send to a client
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    /// volume of the output, 1.0 is the original volume
+    SetVolume(f64),
+    Mute(bool),
+    /// change the output with [`crate::PlaybackClient::change_output`]
+    ChangeOutput { element: String, device: Option<String> },
+    /// latency of the jitterbuffer in ms
+    SetLatency(u32),
+    /// restart the pipeline of the client
+    Restart,
+    /// play a short tone to find the client
+    Identify,
+    /// a SRTP key of the key rotation, wrapped with [`crate::encryption::keyring::wrap_key`].
+    /// with the public key of the server the wrapping key is agreed by [`crate::encryption::KeyExchange`],
+    /// otherwise it is derived from the shared secret
+    AddKey { mki: Vec<u8>, wrapped: Vec<u8>, server_key: Option<Vec<u8>> },
+}
+
+/// A command with its id, the acknowledgement carries the same id
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlMessage {
+    pub id: u32,
+    pub command: ControlCommand,
+}
+
+impl fmt::Display for ControlMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|id={}|", CONTROL_MAGIC, self.id)?;
+        match &self.command {
+            ControlCommand::SetVolume(volume) => write!(f, "cmd=volume|value={}|", volume)?,
+            ControlCommand::Mute(mute) => write!(f, "cmd=mute|value={}|", *mute as u8)?,
+            ControlCommand::ChangeOutput { element, device } => {
+                write!(f, "cmd=output|element={}|", escape(element))?;
+                if let Some(device) = device {
+                    write!(f, "device={}|", escape(device))?;
+                }
+            },
+            ControlCommand::SetLatency(latency) => write!(f, "cmd=latency|value={}|", latency)?,
+            ControlCommand::Restart => write!(f, "cmd=restart|")?,
+            ControlCommand::Identify => write!(f, "cmd=identify|")?,
+            ControlCommand::AddKey { mki, wrapped, server_key } => {
+                write!(f, "cmd=key|mki={}|wrapped={}|", hex::encode(mki), hex::encode(wrapped))?;
+                if let Some(server_key) = server_key {
+                    write!(f, "srv={}|", hex::encode(server_key))?;
+                }
+            },
+        }
+        writeln!(f)
+    }
+}
+
+/// `key=value` fields of a message with the given magic
+fn fields<'a>(s: &'a str, magic: &str) -> Result<Vec<(&'a str, &'a str)>, anyhow::Error> {
+    let mut parts = s.trim_end_matches(['\n', '\r']).split('|');
+    if parts.next() != Some(magic) {
+        return Err(anyhow!("not a {} message", magic));
+    }
+    parts.filter(|field| !field.is_empty())
+        .map(|field| field.split_once('=').ok_or_else(|| anyhow!("invalid field {}", field)))
+        .collect()
+}
+
+impl FromStr for ControlMessage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = fields(s, CONTROL_MAGIC)?;
+        let get = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let value = || get("value").ok_or_else(|| anyhow!("missing value"));
+
+        let id = get("id").ok_or_else(|| anyhow!("missing id"))?.parse()?;
+        let command = match get("cmd").ok_or_else(|| anyhow!("missing command"))? {
+            "volume" => ControlCommand::SetVolume(value()?.parse()?),
+            "mute" => ControlCommand::Mute(value()? == "1"),
+            "output" => ControlCommand::ChangeOutput {
+                element: unescape(get("element").ok_or_else(|| anyhow!("missing element"))?)?,
+                device: get("device").map(unescape).transpose()?,
+            },
+            "latency" => ControlCommand::SetLatency(value()?.parse()?),
+            "restart" => ControlCommand::Restart,
+            "identify" => ControlCommand::Identify,
+            "key" => ControlCommand::AddKey {
+                mki: hex::decode(get("mki").ok_or_else(|| anyhow!("missing mki"))?)?,
+                wrapped: hex::decode(get("wrapped").ok_or_else(|| anyhow!("missing key"))?)?,
+                server_key: get("srv").map(hex::decode).transpose()?,
+            },
+            command => return Err(anyhow!("unknown command {}", command)),
+        };
+
+        Ok(ControlMessage { id, command })
+    }
+}
+
+/// Acknowledgement of a command, with the error if the client could not execute it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlAck {
+    pub id: u32,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for ControlAck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|id={}|", ACK_MAGIC, self.id)?;
+        match &self.error {
+            Some(error) => writeln!(f, "ok=0|error={}|", escape(error)),
+            None => writeln!(f, "ok=1|"),
+        }
+    }
+}
+
+impl FromStr for ControlAck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = fields(s, ACK_MAGIC)?;
+        let get = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+
+        let id = get("id").ok_or_else(|| anyhow!("missing id"))?.parse()?;
+        let error = match get("ok") {
+            Some("1") => None,
+            _ => Some(get("error").map(unescape).transpose()?.unwrap_or_default()),
+        };
+
+        Ok(ControlAck { id, error })
+    }
+}
+
+/// Send a command to a client and wait for the acknowledgement
+///
+/// # Arguments
+/// * `client` - address of the client, the command is send to its control port
+/// * `command` - the command
+/// * `timeout` - how long to wait for the acknowledgement, the command is resend within this time
+/// * `dscp` - DSCP marking of the command, see [`crate::qos`]
+pub fn send_command(client: SocketAddr, command: &ControlCommand, timeout: Duration, dscp: Option<u8>) -> Result<(), anyhow::Error> {
+    let message = ControlMessage {
+        id: NEXT_COMMAND_ID.fetch_add(1, Ordering::Relaxed),
+        command: command.clone(),
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    qos::mark_socket(&socket, dscp);
+
+    let start_instant = Instant::now();
+    let mut buffer = [0u8; 1500];
+    for _ in 0..RETRIES {
+        socket.send_to(auth::sign(&message.to_string()).as_bytes(), client)?;
+
+        let retry_instant = Instant::now();
+        let retry_timeout = timeout / RETRIES;
+        while let Some(remaining) = retry_timeout.checked_sub(retry_instant.elapsed()) {
+            socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+            let (size, from) = match socket.recv_from(&mut buffer) {
+                Ok(res) => res,
+                Err(_) => break,
+            };
+            if from.ip() != client.ip() {
+                continue;
+            }
+
+            let ack = match auth::verify(&String::from_utf8_lossy(&buffer[..size])).map_err(anyhow::Error::from).and_then(|data| data.parse::<ControlAck>()) {
+                Ok(ack) => ack,
+                Err(e) => {
+                    warn!("invalid acknowledgement from {}: {}", from, e);
+                    continue;
+                }
+            };
+            if ack.id != message.id {
+                continue;
+            }
+
+            debug!("{} acknowledged {:?} after {:?}", client, message.command, start_instant.elapsed());
+            return match ack.error {
+                Some(error) => Err(anyhow!("client {} could not execute {:?}: {}", client, message.command, error)),
+                None => Ok(()),
+            };
+        }
+        trace!("no acknowledgement from {} for command {}, resend", client, message.id);
+    }
+
+    Err(anyhow!("client {} did not acknowledge {:?}", client, message.command))
+}
+
+/// Listens for commands on the control port of the client as long as it lives
+pub struct ControlListener {
+    running: Arc<AtomicBool>,
+    local_addr: SocketAddr,
+}
+
+impl ControlListener {
+    /// Listen on the port for commands
+    ///
+    /// # Arguments
+    /// * `port` - normally [`CONTROL_PORT`], 0 for a free port
+    /// * `dscp` - DSCP marking of the acknowledgements, see [`crate::qos`]
+    /// * `handler` - executes the command of the sender, an error is send back in the acknowledgement.
+    ///               it has to refuse commands of other senders than the server
+    pub fn new<F>(port: u16, dscp: Option<u8>, handler: F) -> Result<Self, anyhow::Error>
+    where
+        F: Fn(IpAddr, ControlCommand) -> Result<(), anyhow::Error> + Send + 'static,
+    {
+        let socket = UdpSocket::bind(("0.0.0.0", port))?;
+        socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+        qos::mark_socket(&socket, dscp);
+        let local_addr = socket.local_addr()?;
+        info!("listen for control commands on {}", local_addr);
+
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        thread::spawn(move || {
+            // the last executed command per server, resend commands are only acknowledged again
+            let mut last_executed: Vec<(IpAddr, ControlAck)> = Vec::new();
+            let mut buffer = [0u8; 1500];
+
+            while thread_running.load(Ordering::Relaxed) {
+                let (size, from) = match socket.recv_from(&mut buffer) {
+                    Ok(res) => res,
+                    Err(_) => continue,
+                };
+
+                let message = match auth::verify(&String::from_utf8_lossy(&buffer[..size])).map_err(anyhow::Error::from).and_then(|data| data.parse::<ControlMessage>()) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("reject control message from {}: {}", from, e);
+                        continue;
+                    }
+                };
+
+                let ack = match last_executed.iter().find(|(addr, ack)| *addr == from.ip() && ack.id == message.id) {
+                    Some((_, ack)) => ack.clone(),
+                    None => {
+                        info!("execute {:?} from {}", message.command, from);
+                        let ack = ControlAck {
+                            id: message.id,
+                            error: handler(from.ip(), message.command).err().map(|e| e.to_string()),
+                        };
+                        last_executed.retain(|(addr, _)| *addr != from.ip());
+                        last_executed.push((from.ip(), ack.clone()));
+                        ack
+                    }
+                };
+
+                if let Err(e) = socket.send_to(auth::sign(&ack.to_string()).as_bytes(), from) {
+                    warn!("could not acknowledge command {} to {}: {:?}", ack.id, from, e);
+                }
+            }
+            debug!("stop control listener on {}", local_addr);
+        });
+
+        Ok(ControlListener { running, local_addr })
+    }
+
+    /// address where the listener receives the commands
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for ControlListener {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    fn round_trip(command: ControlCommand) {
+        let message = ControlMessage { id: 42, command };
+        assert_eq!(message.to_string().parse::<ControlMessage>().unwrap(), message);
+    }
+
+    #[test]
+    fn message_round_trip() {
+        round_trip(ControlCommand::SetVolume(0.5));
+        round_trip(ControlCommand::Mute(true));
+        round_trip(ControlCommand::Mute(false));
+        round_trip(ControlCommand::ChangeOutput { element: "alsasink".to_string(), device: Some("hw:0,0|x=%".to_string()) });
+        round_trip(ControlCommand::ChangeOutput { element: "pulsesink".to_string(), device: None });
+        round_trip(ControlCommand::SetLatency(1500));
+        round_trip(ControlCommand::Restart);
+        round_trip(ControlCommand::Identify);
+        round_trip(ControlCommand::AddKey { mki: vec![0, 0, 0, 2], wrapped: vec![1, 2, 3], server_key: Some(vec![9; 32]) });
+        round_trip(ControlCommand::AddKey { mki: vec![0, 0, 0, 2], wrapped: vec![1, 2, 3], server_key: None });
+    }
+
+    #[test]
+    fn message_errors() {
+        assert!("micast-ack|id=1|ok=1|".parse::<ControlMessage>().is_err());
+        assert!("micast-ctl|cmd=restart|".parse::<ControlMessage>().is_err());
+        assert!("micast-ctl|id=1|cmd=unknown|".parse::<ControlMessage>().is_err());
+        assert!("micast-ctl|id=1|cmd=volume|".parse::<ControlMessage>().is_err());
+    }
+
+    #[test]
+    fn ack_round_trip() {
+        let ok = ControlAck { id: 7, error: None };
+        assert_eq!(ok.to_string().parse::<ControlAck>().unwrap(), ok);
+
+        let error = ControlAck { id: 8, error: Some("no device a|b".to_string()) };
+        assert_eq!(error.to_string().parse::<ControlAck>().unwrap(), error);
+    }
+
+    #[test]
+    fn command_over_loopback() {
+        let listener = ControlListener::new(0, None, |from, command| match command {
+            _ if !from.is_loopback() => Err(anyhow!("not the server")),
+            ControlCommand::SetLatency(latency) if latency > 10000 => Err(anyhow!("latency too high")),
+            _ => Ok(()),
+        }).unwrap();
+        let client: SocketAddr = ([127, 0, 0, 1], listener.local_addr().port()).into();
+
+        send_command(client, &ControlCommand::SetLatency(500), Duration::from_secs(3), None).unwrap();
+
+        let error = send_command(client, &ControlCommand::SetLatency(20000), Duration::from_secs(3), None).unwrap_err();
+        assert!(error.to_string().contains("latency too high"));
+    }
+
+    #[test]
+    fn resent_command_is_executed_once() {
+        let executed = Arc::new(AtomicUsize::new(0));
+        let counter = executed.clone();
+        let listener = ControlListener::new(0, None, move |_, _| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }).unwrap();
+        let client: SocketAddr = ([127, 0, 0, 1], listener.local_addr().port()).into();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(3))).unwrap();
+        let message = ControlMessage { id: 5, command: ControlCommand::Identify };
+        let mut buffer = [0u8; 1500];
+        for _ in 0..2 {
+            socket.send_to(auth::sign(&message.to_string()).as_bytes(), client).unwrap();
+            let size = socket.recv(&mut buffer).unwrap();
+            let ack = auth::verify(&String::from_utf8_lossy(&buffer[..size])).unwrap().parse::<ControlAck>().unwrap();
+            assert_eq!(ack, ControlAck { id: 5, error: None });
+        }
+
+        assert_eq!(executed.load(Ordering::Relaxed), 1);
+    }
+}
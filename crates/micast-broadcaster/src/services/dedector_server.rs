@@ -1,9 +1,15 @@
 
+use super::discovery::Announcement;
+use super::InterfaceFilter;
+
 /// #service sends the ip address of the server to the clients
 /// 
 /// This function is called by the main thread and is used to send the ip address of the server to the clients.
 /// # Arguments
-/// * `tcp_port` - the rtp port of the server
-pub fn service(rtp_port: u32) -> Result<(), anyhow::Error> {
-    Ok(super::informip::inform_clients(rtp_port))
+/// * `announcement` - the announcement of the server, see [`Announcement`]
+/// * `filter` - interfaces where the announcement is send to
+/// * `dscp` - DSCP marking of the announcements, see [`crate::qos`]
+pub fn service(announcement: Announcement, filter: InterfaceFilter, dscp: Option<u8>) -> Result<(), anyhow::Error> {
+    super::informip::answer_probes(announcement.clone(), dscp);
+    Ok(super::informip::inform_clients(announcement, filter, dscp))
 }
\ No newline at end of file
@@ -0,0 +1,530 @@
+/// Discovery message which the server broadcasts to the clients
+///
+/// format of version 1, all values are escaped:
+/// `micast-dj|v=1|id=<server id>|name=<name>|rtp=<port>|clock=<port>|codec=<codec>|mcast=<group>|channels=<a>,<b>|zone=<zone>|prio=<priority>|srtp=<0/1>|suite=<srtp crypto suite>|`
+///
+/// the legacy format `micast-dj|NOMULTICAST|<rtp port>|` is parsed as version 0
+/// 
+/// the clients find the server via broadcast or mDNS, see [`discover`]
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use crossbeam_channel::bounded;
+use log::trace;
+
+use super::InterfaceFilter;
+
+use crate::clock::DEFAULT_NET_TIME_PORT;
+use crate::encryption::SrtpCipherSuite;
+
+/// first field of every discovery message
+pub const DISCOVERY_MAGIC: &str = "micast-dj";
+/// version of the discovery message which is send by this server
+pub const DISCOVERY_VERSION: u32 = 1;
+
+const LEGACY_NO_MULTICAST: &str = "NOMULTICAST";
+
+/// Announcement of a server, send via broadcast to the clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct Announcement {
+    /// protocol version, 0 for the legacy format
+    pub version: u32,
+    /// unique id of the server, empty for the legacy format
+    pub server_id: String,
+    /// human readable name of the server
+    pub name: String,
+    /// port where the RTP stream is send to
+    pub rtp_port: u32,
+    /// port of the net time provider, `None` if the server uses PTP or NTP
+    pub clock_port: Option<u32>,
+    /// encoding name of the stream, e.g. `OPUS` or `L24`
+    pub codec: String,
+    /// multicast group of the stream, `None` for unicast streams
+    pub multicast_group: Option<String>,
+    /// names of the channels the server provides
+    pub channels: Vec<String>,
+    /// zone of the server, e.g. a room or a floor
+    pub zone: Option<String>,
+    /// priority of the server, the server with the highest priority is preferred
+    pub priority: u32,
+    /// the stream is encrypted with SRTP, legacy servers never encrypt
+    pub encrypted: bool,
+    /// name of the SRTP crypto suite of an encrypted stream, newer servers may use a suite
+    /// this version does not know, see [`Announcement::srtp_cipher_suite`]
+    pub cipher_suite: String,
+}
+
+impl Announcement {
+    /// the SRTP crypto suite of the stream, fails if the suite is unknown and the stream can not be decrypted
+    pub fn srtp_cipher_suite(&self) -> Result<SrtpCipherSuite, anyhow::Error> {
+        self.cipher_suite.parse()
+    }
+
+    pub fn new(server_id: &str, name: &str, rtp_port: u32) -> Self {
+        Announcement {
+            version: DISCOVERY_VERSION,
+            server_id: server_id.to_string(),
+            name: name.to_string(),
+            rtp_port,
+            clock_port: Some(DEFAULT_NET_TIME_PORT as u32),
+            codec: "OPUS".to_string(),
+            multicast_group: None,
+            channels: Vec::new(),
+            zone: None,
+            priority: 0,
+            encrypted: false,
+            cipher_suite: SrtpCipherSuite::default().to_string(),
+        }
+    }
+
+    /// parse the legacy format `micast-dj|NOMULTICAST|<rtp port>|`
+    fn parse_legacy(fields: &[&str]) -> Result<Self, anyhow::Error> {
+        let multicast_group = fields.get(1).ok_or_else(|| anyhow!("missing multicast field"))?;
+        let rtp_port = fields.get(2)
+            .ok_or_else(|| anyhow!("missing rtp port"))?
+            .trim()
+            .parse::<u32>()?;
+
+        Ok(Announcement {
+            version: 0,
+            server_id: String::new(),
+            name: DISCOVERY_MAGIC.to_string(),
+            rtp_port,
+            clock_port: Some(DEFAULT_NET_TIME_PORT as u32),
+            codec: "OPUS".to_string(),
+            multicast_group: match *multicast_group {
+                LEGACY_NO_MULTICAST | "" => None,
+                group => Some(group.to_string()),
+            },
+            channels: Vec::new(),
+            zone: None,
+            priority: 0,
+            encrypted: false,
+            cipher_suite: SrtpCipherSuite::default().to_string(),
+        })
+    }
+}
+
+/// escape the separators of the message
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '%' | '|' | '=' | ',' | '\n' | '\r' => escaped.push_str(&format!("%{:02X}", c as u8)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// unescape a value escaped by [`escape`]
+pub(crate) fn unescape(value: &str) -> Result<String, anyhow::Error> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [
+                iter.next().ok_or_else(|| anyhow!("incomplete escape sequence"))?,
+                iter.next().ok_or_else(|| anyhow!("incomplete escape sequence"))?,
+            ];
+            let hex = std::str::from_utf8(&hex)?;
+            bytes.push(u8::from_str_radix(hex, 16)?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    Ok(String::from_utf8(bytes)?)
+}
+
+impl fmt::Display for Announcement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|v={}|id={}|name={}|rtp={}|", DISCOVERY_MAGIC, self.version, escape(&self.server_id), escape(&self.name), self.rtp_port)?;
+        if let Some(clock_port) = self.clock_port {
+            write!(f, "clock={}|", clock_port)?;
+        }
+        write!(f, "codec={}|", escape(&self.codec))?;
+        if let Some(multicast_group) = &self.multicast_group {
+            write!(f, "mcast={}|", escape(multicast_group))?;
+        }
+        if !self.channels.is_empty() {
+            let channels: Vec<String> = self.channels.iter().map(|c| escape(c)).collect();
+            write!(f, "channels={}|", channels.join(","))?;
+        }
+        if let Some(zone) = &self.zone {
+            write!(f, "zone={}|", escape(zone))?;
+        }
+        if self.priority != 0 {
+            write!(f, "prio={}|", self.priority)?;
+        }
+        if self.encrypted {
+            write!(f, "srtp=1|suite={}|", escape(&self.cipher_suite))?;
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for Announcement {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.trim_end_matches(['\n', '\r']).split('|').collect();
+        if fields.first() != Some(&DISCOVERY_MAGIC) {
+            return Err(anyhow!("not a micast discovery message"));
+        }
+
+        match fields.get(1) {
+            Some(field) if field.starts_with("v=") => {},
+            _ => return Self::parse_legacy(&fields),
+        }
+
+        let mut announcement = Announcement::new("", "", 0);
+        announcement.clock_port = None;
+        let mut has_rtp_port = false;
+
+        for field in fields.iter().skip(1).filter(|field| !field.is_empty()) {
+            let (key, value) = field.split_once('=').ok_or_else(|| anyhow!("invalid field {}", field))?;
+            match key {
+                "v" => announcement.version = value.parse()?,
+                "id" => announcement.server_id = unescape(value)?,
+                "name" => announcement.name = unescape(value)?,
+                "rtp" => {
+                    announcement.rtp_port = value.parse()?;
+                    has_rtp_port = true;
+                },
+                "clock" => announcement.clock_port = Some(value.parse()?),
+                "codec" => announcement.codec = unescape(value)?,
+                "mcast" => announcement.multicast_group = Some(unescape(value)?),
+                "channels" => {
+                    announcement.channels = value.split(',')
+                        .filter(|c| !c.is_empty())
+                        .map(unescape)
+                        .collect::<Result<_, _>>()?;
+                },
+                "zone" => announcement.zone = Some(unescape(value)?),
+                "prio" => announcement.priority = value.parse()?,
+                "srtp" => announcement.encrypted = value == "1",
+                "suite" => announcement.cipher_suite = unescape(value)?,
+                // fields of newer versions
+                _ => trace!("ignore unknown discovery field {}", key),
+            }
+        }
+
+        if !has_rtp_port {
+            return Err(anyhow!("missing rtp port"));
+        }
+
+        Ok(announcement)
+    }
+}
+
+/// How the client searches for the server
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DiscoveryMethod {
+    /// listen for the broadcast announcements on port 5889
+    #[default]
+    Broadcast,
+    /// browse for the `_micast._udp` service via mDNS, works across VLANs with an mDNS reflector
+    Mdns,
+    /// use both, the first found server wins
+    Both,
+}
+
+/// Wait a specific Duration for a server with the given method
+/// 
+/// # Arguments
+/// * `timeout` - how long to wait
+/// * `method` - broadcast, mDNS or both
+/// * `filter` - interfaces where the broadcast is received
+/// * `accept` - servers whose announcement is not accepted are ignored, e.g. for the encryption policy
+/// 
+/// # Returns
+/// Option<(IpAddr, Announcement)> - the ip address of the server and its announcement
+pub fn discover<F>(timeout: Duration, method: DiscoveryMethod, filter: &InterfaceFilter, accept: F) -> Option<(IpAddr, Announcement)>
+where
+    F: Fn(&Announcement) -> bool + Clone + Send + 'static,
+{
+    match method {
+        DiscoveryMethod::Broadcast => super::wait_for_broadcast(timeout, filter, accept),
+        DiscoveryMethod::Mdns => super::mdns::wait_for_mdns(timeout, accept),
+        DiscoveryMethod::Both => {
+            let (sender, receiver) = bounded(2);
+
+            let mdns_sender = sender.clone();
+            let mdns_accept = accept.clone();
+            thread::spawn(move || {
+                let _ = mdns_sender.send(super::mdns::wait_for_mdns(timeout, mdns_accept));
+            });
+            let filter = filter.clone();
+            thread::spawn(move || {
+                let _ = sender.send(super::wait_for_broadcast(timeout, &filter, accept));
+            });
+
+            // the first found server wins, the other search ends with its timeout
+            receiver.iter().take(2).flatten().next()
+        }
+    }
+}
+
+/// A server seen during the discovery
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredServer {
+    /// address where the announcement comes from
+    pub address: IpAddr,
+    pub announcement: Announcement,
+    /// when the last announcement of the server was received
+    pub last_seen: Instant,
+}
+
+/// All servers seen within the window with the given method
+/// 
+/// servers are identified by their server id or by their address for legacy announcements
+pub fn collect_servers(window: Duration, method: DiscoveryMethod, filter: &InterfaceFilter) -> Vec<DiscoveredServer> {
+    let found = match method {
+        DiscoveryMethod::Broadcast => super::collect_broadcasts(window, filter),
+        DiscoveryMethod::Mdns => super::mdns::discover_mdns(window),
+        DiscoveryMethod::Both => {
+            let mdns = thread::spawn(move || super::mdns::discover_mdns(window));
+            let mut found = super::collect_broadcasts(window, filter);
+            found.extend(mdns.join().unwrap_or_default());
+            found
+        }
+    };
+    merge_servers(found)
+}
+
+/// one entry for every server, the last announcement of a server wins and the servers keep the order they were first seen
+fn merge_servers(found: Vec<(IpAddr, Announcement)>) -> Vec<DiscoveredServer> {
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    for (address, announcement) in found {
+        let known = servers.iter_mut().find(|server| {
+            if announcement.server_id.is_empty() {
+                server.address == address
+            } else {
+                server.announcement.server_id == announcement.server_id
+            }
+        });
+        match known {
+            Some(server) => {
+                server.address = address;
+                server.announcement = announcement;
+                server.last_seen = Instant::now();
+            },
+            None => servers.push(DiscoveredServer { address, announcement, last_seen: Instant::now() }),
+        }
+    }
+    servers
+}
+
+/// Policy to choose a server if several servers are found
+/// 
+/// only servers of the zone are considered if a zone is set.
+/// the preferred server is choosen if it is found, otherwise the one with the highest priority
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerSelection {
+    /// id of the preferred server
+    pub preferred_server_id: Option<String>,
+    /// only use servers of this zone
+    pub zone: Option<String>,
+    /// how long the servers are collected before one is choosen, at least [`MIN_SELECTION_WINDOW`]
+    pub window: Duration,
+}
+
+/// shortest window of the [`ServerSelection`], shorter windows are extended
+pub const MIN_SELECTION_WINDOW: Duration = Duration::from_millis(500);
+
+impl Default for ServerSelection {
+    fn default() -> Self {
+        ServerSelection {
+            preferred_server_id: None,
+            zone: None,
+            window: Duration::from_secs(6),
+        }
+    }
+}
+
+impl ServerSelection {
+    /// choose a server, `None` if no server matches the zone
+    pub fn select<'a>(&self, servers: &'a [DiscoveredServer]) -> Option<&'a DiscoveredServer> {
+        let candidates: Vec<&DiscoveredServer> = servers.iter()
+            .filter(|server| match &self.zone {
+                Some(zone) => server.announcement.zone.as_ref() == Some(zone),
+                None => true,
+            })
+            .collect();
+
+        if let Some(preferred_server_id) = &self.preferred_server_id {
+            if let Some(server) = candidates.iter().find(|server| &server.announcement.server_id == preferred_server_id) {
+                return Some(server);
+            }
+        }
+
+        // max_by_key returns the last of equal elements, the first seen should win
+        candidates.into_iter().rev().max_by_key(|server| server.announcement.priority)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcement_round_trip() {
+        let mut announcement = Announcement::new("server-1", "Living | Room = 100%", 5004);
+        announcement.clock_port = None;
+        announcement.codec = "L24".to_string();
+        announcement.multicast_group = Some("239.69.1.1".to_string());
+        announcement.channels = vec!["left,front".to_string(), "right".to_string()];
+        announcement.zone = Some("first floor".to_string());
+        announcement.priority = 3;
+        announcement.encrypted = true;
+
+        let message = announcement.to_string();
+        assert!(message.ends_with("|\n"));
+        assert_eq!(message.parse::<Announcement>().unwrap(), announcement);
+    }
+
+    #[test]
+    fn announcement_defaults() {
+        let announcement = "micast-dj|v=1|id=a|name=b|rtp=5004|".parse::<Announcement>().unwrap();
+        assert_eq!(announcement.clock_port, None);
+        assert_eq!(announcement.codec, "OPUS");
+        assert!(announcement.channels.is_empty());
+        assert!(!announcement.encrypted);
+
+        // fields of newer versions are ignored
+        let announcement = "micast-dj|v=2|id=a|rtp=5004|new=field|\n".parse::<Announcement>().unwrap();
+        assert_eq!(announcement.version, 2);
+        assert_eq!(announcement.rtp_port, 5004);
+    }
+
+    #[test]
+    fn announcement_errors() {
+        assert!("other|v=1|rtp=5004|".parse::<Announcement>().is_err());
+        assert!("micast-dj|v=1|id=a|".parse::<Announcement>().is_err());
+        assert!("micast-dj|v=1|rtp=port|".parse::<Announcement>().is_err());
+        assert!("micast-dj|v=1|rtp=5004|field|".parse::<Announcement>().is_err());
+        assert!("micast-dj|v=1|rtp=5004|name=%4|".parse::<Announcement>().is_err());
+    }
+
+    #[test]
+    fn legacy_announcement() {
+        let announcement = "micast-dj|NOMULTICAST|5004|\n".parse::<Announcement>().unwrap();
+        assert_eq!(announcement.version, 0);
+        assert_eq!(announcement.rtp_port, 5004);
+        assert_eq!(announcement.multicast_group, None);
+        assert_eq!(announcement.clock_port, Some(DEFAULT_NET_TIME_PORT as u32));
+
+        let announcement = "micast-dj|239.1.1.1|5006|".parse::<Announcement>().unwrap();
+        assert_eq!(announcement.multicast_group.as_deref(), Some("239.1.1.1"));
+
+        assert!("micast-dj|NOMULTICAST|".parse::<Announcement>().is_err());
+        assert!("micast-dj|NOMULTICAST|port|".parse::<Announcement>().is_err());
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        let value = "a|b=c,d%e\r\nf ü";
+        let escaped = escape(value);
+        assert!(!escaped.contains(['|', '=', ',', '\n', '\r']));
+        assert_eq!(unescape(&escaped).unwrap(), value);
+        assert_eq!(escape("plain"), "plain");
+
+        assert!(unescape("%7").is_err());
+        assert!(unescape("%zz").is_err());
+        assert!(unescape("%FF").is_err());
+    }
+
+    fn announcement(server_id: &str, zone: Option<&str>, priority: u32) -> Announcement {
+        let mut announcement = Announcement::new(server_id, server_id, 5004);
+        announcement.zone = zone.map(str::to_string);
+        announcement.priority = priority;
+        announcement
+    }
+
+    fn servers(announcements: Vec<Announcement>) -> Vec<DiscoveredServer> {
+        let found = announcements.into_iter().enumerate()
+            .map(|(i, announcement)| (IpAddr::from([192, 168, 1, i as u8 + 1]), announcement))
+            .collect();
+        merge_servers(found)
+    }
+
+    fn selected(selection: &ServerSelection, servers: &[DiscoveredServer]) -> Option<String> {
+        selection.select(servers).map(|server| server.announcement.server_id.clone())
+    }
+
+    #[test]
+    fn select_by_priority() {
+        let servers = servers(vec![
+            announcement("a", None, 1),
+            announcement("b", None, 5),
+            announcement("c", None, 5),
+            announcement("d", None, 2),
+        ]);
+        // the first seen wins on equal priority
+        assert_eq!(selected(&ServerSelection::default(), &servers).as_deref(), Some("b"));
+        assert_eq!(selected(&ServerSelection::default(), &[]), None);
+    }
+
+    #[test]
+    fn select_preferred_server() {
+        let servers = servers(vec![announcement("a", None, 1), announcement("b", None, 5)]);
+        let selection = ServerSelection { preferred_server_id: Some("a".to_string()), ..Default::default() };
+        assert_eq!(selected(&selection, &servers).as_deref(), Some("a"));
+
+        // the priority decides if the preferred server is not found
+        let selection = ServerSelection { preferred_server_id: Some("x".to_string()), ..Default::default() };
+        assert_eq!(selected(&selection, &servers).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn select_by_zone() {
+        let servers = servers(vec![
+            announcement("a", Some("kitchen"), 1),
+            announcement("b", None, 5),
+            announcement("c", Some("office"), 9),
+            announcement("d", Some("kitchen"), 2),
+        ]);
+        let selection = ServerSelection { zone: Some("kitchen".to_string()), ..Default::default() };
+        assert_eq!(selected(&selection, &servers).as_deref(), Some("d"));
+
+        // the preferred server has to be in the zone
+        let selection = ServerSelection { zone: Some("kitchen".to_string()), preferred_server_id: Some("c".to_string()), ..Default::default() };
+        assert_eq!(selected(&selection, &servers).as_deref(), Some("d"));
+
+        let selection = ServerSelection { zone: Some("garden".to_string()), ..Default::default() };
+        assert_eq!(selected(&selection, &servers), None);
+    }
+
+    #[test]
+    fn merge_by_server_id() {
+        let address = IpAddr::from([192, 168, 1, 1]);
+        let moved = IpAddr::from([192, 168, 1, 2]);
+        let servers = merge_servers(vec![
+            (address, announcement("a", None, 1)),
+            (address, announcement("b", None, 1)),
+            (moved, announcement("a", None, 3)),
+        ]);
+        assert_eq!(servers.len(), 2);
+        assert_eq!(servers[0].announcement.server_id, "a");
+        assert_eq!(servers[0].address, moved);
+        assert_eq!(servers[0].announcement.priority, 3);
+        assert_eq!(servers[1].announcement.server_id, "b");
+    }
+
+    #[test]
+    fn merge_legacy_by_address() {
+        let legacy = "micast-dj|NOMULTICAST|5004|".parse::<Announcement>().unwrap();
+        let first = IpAddr::from([192, 168, 1, 1]);
+        let second = IpAddr::from([192, 168, 1, 2]);
+        let servers = merge_servers(vec![
+            (first, legacy.clone()),
+            (second, legacy.clone()),
+            (first, legacy.clone()),
+        ]);
+        assert_eq!(servers.iter().map(|server| server.address).collect::<Vec<_>>(), vec![first, second]);
+    }
+}
@@ -2,64 +2,121 @@
 // 
 // 
 use crate::sleep_ms;
-use std::net::{IpAddr, UdpSocket, Ipv4Addr};
+use crate::qos;
+use super::discovery::Announcement;
+use super::access::{Credential, CredentialProof};
+use super::auth;
+use super::report::ClientReport;
+use crate::encryption::exchange::PUBLIC_KEY_LENGTH;
+use std::net::{IpAddr, UdpSocket, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use crossbeam_channel::{Sender, Receiver};
 use crossbeam_channel::unbounded;
 use std::thread;
 use std::time::Duration;
 
-use local_ip_address::list_afinet_netifas;
+use if_addrs::{get_if_addrs, IfAddr};
+use socket2::{Socket, Domain, Type, Protocol};
 use log::{info, trace, warn, debug};
 
 
 
 const BROADCAST_PORT:u16 = 5889;
 const CONFIRMATION_PORT:u16 = 5887;
+const PROBE_PORT:u16 = 5888;
+const PROBE_MESSAGE: &str = "micast-probe|\n";
+pub(crate) const CONFIRM_MAGIC: &str = "mirror";
 
-pub fn inform_clients(rtp_port: u32) {
+/// Allow and deny list of network interfaces used for the discovery
+/// 
+/// an entry matches the interface name exactly or as prefix if it ends with `*`, e.g. `eth*`.
+/// an empty allow list allows all interfaces, the deny list wins over the allow list
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InterfaceFilter {
+    pub allow: Vec<String>,
+    pub deny: Vec<String>,
+}
 
+impl InterfaceFilter {
+    /// the interface with the name is allowed
+    pub fn matches(&self, name: &str) -> bool {
+        let matches_entry = |entry: &String| match entry.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == entry,
+        };
 
-    let content = format!("micast-dj|NOMULTICAST|{}|\n", rtp_port);
+        (self.allow.is_empty() || self.allow.iter().any(matches_entry)) && !self.deny.iter().any(matches_entry)
+    }
+}
 
-    thread::spawn(move || {
+/// IPv4 network of an interface
+#[derive(Debug, Clone, Copy)]
+struct Network {
+    ip: Ipv4Addr,
+    netmask: Ipv4Addr,
+    broadcast: Ipv4Addr,
+}
 
-        let content = &content;
+impl Network {
+    fn contains(&self, ip: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(self.ip) & mask == u32::from(ip) & mask
+    }
+}
 
-        loop {
+/// directed broadcast address of the network, e.g. 10.0.1.255 for 10.0.0.5/23
+fn directed_broadcast(ip: Ipv4Addr, netmask: Ipv4Addr) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(ip) | !u32::from(netmask))
+}
 
-            let ifas = list_afinet_netifas().unwrap();
-
-            for (name, ipaddr) in ifas {
-                if matches!(ipaddr, IpAddr::V4(_)) && (!name.contains("lo") || ipaddr.is_loopback() == false ) && ipaddr.is_ipv4() {
-                    let broadcast_ip = { 
-                        let ip =  match ipaddr {
-                            IpAddr::V4(ip) => {
-                                ip
-                            }, 
-                            _ => break
-                        };
-                        let mut temp = ip.octets();
-                        temp[3] = 0xff;
-                        Ipv4Addr::from(temp)
-                    };
-                    
-                    debug!("send micast-dj info for over {}", broadcast_ip);
-                    //let socket = UdpSocket::bind(format!("0.0.0.0:{}", BROADCAST_PORT)).unwrap();
-                    let try_socket = UdpSocket::bind("0.0.0.0:0");
-                    if let Ok(socket) = try_socket {
-                        socket.set_read_timeout(Some(std::time::Duration::from_secs(5))).unwrap();
-                        socket.set_broadcast(true).unwrap();
-                        let _ = socket.connect((broadcast_ip, BROADCAST_PORT));
-                        let res = socket.send(content.as_bytes());
-                        if res.is_err() {
-                            // try to reconnect...
-                            println!("think we got an error... {:?}", res);
-                        }
+/// IPv4 networks of all allowed interfaces without loopback
+fn networks(filter: &InterfaceFilter) -> Vec<(String, Network)> {
+    let interfaces = match get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            warn!("could not list network interfaces: {:?}", e);
+            return Vec::new();
+        }
+    };
+
+    interfaces.into_iter()
+        .filter(|iface| !iface.is_loopback() && filter.matches(&iface.name))
+        .filter_map(|iface| match iface.addr {
+            IfAddr::V4(addr) => {
+                let broadcast = addr.broadcast.unwrap_or_else(|| directed_broadcast(addr.ip, addr.netmask));
+                Some((iface.name, Network { ip: addr.ip, netmask: addr.netmask, broadcast }))
+            },
+            IfAddr::V6(_) => None,
+        })
+        .collect()
+}
+
+/// Announce the server every 5 seconds via broadcast on all allowed interfaces,
+/// the packets are marked with `dscp`
+pub fn inform_clients(announcement: Announcement, filter: InterfaceFilter, dscp: Option<u8>) {
 
-                    } else {
-                        warn!("error on create socket for server inform clients: {:?}", try_socket.err());
-                        sleep_ms!(500);
+
+    let content = announcement.to_string();
+
+    thread::spawn(move || {
+
+        loop {
+
+            for (name, network) in networks(&filter) {
+                // signed per packet, every packet needs its own nonce
+                let content = auth::sign(&content);
+                debug!("send micast-dj info for over {} ({})", network.broadcast, name);
+                let try_socket = UdpSocket::bind((network.ip, 0));
+                if let Ok(socket) = try_socket {
+                    socket.set_broadcast(true).unwrap();
+                    qos::mark_socket(&socket, dscp);
+                    let res = socket.send_to(content.as_bytes(), (network.broadcast, BROADCAST_PORT));
+                    if let Err(e) = res {
+                        warn!("could not announce the server over {} ({}): {:?}", network.broadcast, name, e);
                     }
+
+                } else {
+                    warn!("error on create socket for server inform clients: {:?}", try_socket.err());
+                    sleep_ms!(500);
                 }
             }
 
@@ -70,86 +127,258 @@ pub fn inform_clients(rtp_port: u32) {
 
 }
 
+/// one socket on all interfaces for receiving the broadcast messages
+fn bind_broadcast_socket() -> Result<UdpSocket, anyhow::Error> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_broadcast(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, BROADCAST_PORT)).into())?;
+    Ok(socket.into())
+}
+
 
 /// Wait a specific Duration for a broadcast message
 /// 
+/// only messages from the networks of the allowed interfaces are accepted
+/// 
+/// # Arguments
+/// * `timeout` - how long to wait
+/// * `filter` - interfaces where the broadcast is received
+/// * `accept` - servers whose announcement is not accepted are ignored, e.g. for the encryption policy
+/// 
 /// # Returns
-/// Option<(IpAddr, String)> - the ip address where the broadcast comes from and the data where RTP Streams are send to
-pub fn wait_for_broadcast(timeout: std::time::Duration) -> Option<(IpAddr, String)> {
+/// Option<(IpAddr, Announcement)> - the ip address where the broadcast comes from and the announcement of the server
+pub fn wait_for_broadcast(timeout: std::time::Duration, filter: &InterfaceFilter, accept: impl Fn(&Announcement) -> bool) -> Option<(IpAddr, Announcement)> {
+    let mut found = None;
+    receive_broadcasts(timeout, filter, |addr, announcement| {
+        if !accept(&announcement) {
+            debug!("ignore server {} ({}), announcement not accepted", addr, announcement.server_id);
+            return false;
+        }
+        found = Some((addr, announcement));
+        true
+    });
+    found
+}
+
+/// All broadcast messages received within the window
+/// 
+/// every server sends its announcement every 5 seconds, the window should be longer
+pub fn collect_broadcasts(window: std::time::Duration, filter: &InterfaceFilter) -> Vec<(IpAddr, Announcement)> {
+    let mut found = Vec::new();
+    receive_broadcasts(window, filter, |addr, announcement| {
+        found.push((addr, announcement));
+        false
+    });
+    found
+}
+
+/// Receive broadcast messages until the timeout or `on_announcement` returns true
+fn receive_broadcasts(timeout: std::time::Duration, filter: &InterfaceFilter, mut on_announcement: impl FnMut(IpAddr, Announcement) -> bool) {
     let start_instant = std::time::Instant::now();
     while start_instant.elapsed() < timeout {
 
-        let ifas = list_afinet_netifas().unwrap();
-        for (name, ipaddr) in ifas {
-            if matches!(ipaddr, IpAddr::V4(_)) && (!name.contains("lo") || ipaddr.is_loopback() == false ) && ipaddr.is_ipv4() {
-                // make broadcast ip
-                let broadcast_ip = { 
-                    let ip =  match ipaddr {
-                        IpAddr::V4(ip) => {
-                            ip
-                        }, 
-                        _ => break
-                    };
-                    let mut temp = ip.octets();
-                    temp[3] = 0xff;
-                    let broadcast_ip = Ipv4Addr::from(temp);
-                    broadcast_ip
-                };
-                
-                let try_socket = UdpSocket::bind((broadcast_ip, BROADCAST_PORT));
-                if let Ok(socket) = try_socket {
-                    trace!("listen on socket {:?} for ip {}", socket.local_addr().unwrap(), broadcast_ip);
-                    socket.set_read_timeout(Some(std::time::Duration::from_millis(500))).unwrap();
-                    socket.set_broadcast(true).unwrap();
+        let socket = match bind_broadcast_socket() {
+            Ok(socket) => socket,
+            Err(e) => {
+                trace!("error on create socket for broadcast: {:?}", e);
+                sleep_ms!(200);
+                continue;
+            }
+        };
+        trace!("listen on socket {:?}", socket.local_addr());
+
+        let networks = networks(filter);
+        let mut buffer = [0u8; 1500];
+        while start_instant.elapsed() < timeout {
+            let remaining = timeout.saturating_sub(start_instant.elapsed()).max(Duration::from_millis(1));
+            let _ = socket.set_read_timeout(Some(remaining));
+
+            let (size, addr) = match socket.recv_from(&mut buffer) {
+                Ok(res) => res,
+                Err(e) => {
+                    trace!("error on recv from broadcast: {:?}", e);
+                    continue;
+                }
+            };
+
+            let from_allowed_network = match addr.ip() {
+                IpAddr::V4(ip) => ip.is_loopback() || networks.iter().any(|(_, network)| network.contains(ip)),
+                IpAddr::V6(_) => false,
+            };
+            if !from_allowed_network {
+                trace!("ignore datagramm from {}, not from an allowed interface", addr);
+                continue;
+            }
 
-                    let mut buffer = [0u8; 256];
-                    let res = socket.recv_from(&mut buffer);
-                    match res {
-                        Ok((size, addr)) => {
-                            let data = std::str::from_utf8(&buffer[..size]).unwrap();
-                            let d: Vec<&str> = data.split("|").collect();
-                            if d.len() < 1 {
-                                warn!("received datagramm from {} with wrong data {}", addr, data);
-                                break
-                            }
-                            info!("received datagramm from {} with {}", addr, data);
-                            return Some((addr.clone().ip(), d[1].to_string()))
-                        },
-                        Err(e) => {
-                            trace!("error on recv from broadcast: {:?}", e);
-                        }
+            let data = match auth::verify(&String::from_utf8_lossy(&buffer[..size])) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("reject datagramm from {}: {}", addr, e);
+                    continue;
+                }
+            };
+            match data.parse::<Announcement>() {
+                Ok(announcement) => {
+                    info!("received datagramm from {} with {:?}", addr, announcement);
+                    if on_announcement(addr.ip(), announcement) {
+                        return
                     }
-                } else {
-                    trace!("error on create socket for broadcast: {:?}", try_socket.err().unwrap());
-                    sleep_ms!(200);
+                },
+                Err(e) => {
+                    warn!("received datagramm from {} with wrong data {}: {}", addr, data, e);
+                }
+            }
+        }
+    }
+}
+
+
+/// Answer unicast probes of clients with the announcement, for clients in other subnets
+/// where the broadcast does not reach, the answers are marked with `dscp`
+pub fn answer_probes(announcement: Announcement, dscp: Option<u8>) {
+    let content = announcement.to_string();
+
+    thread::spawn(move || {
+        loop {
+            let socket = match UdpSocket::bind(("0.0.0.0", PROBE_PORT)) {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("error on create socket for probes on port {}: {:?}", PROBE_PORT, e);
+                    thread::sleep(Duration::from_secs(5));
+                    continue;
                 }
+            };
+            qos::mark_socket(&socket, dscp);
+            info!("answer probes on port {}", PROBE_PORT);
+
+            let mut buffer = [0u8; 1500];
+            loop {
+                let (size, addr) = match socket.recv_from(&mut buffer) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        warn!("error on recv probe: {:?}", e);
+                        break;
+                    }
+                };
 
+                match auth::verify(&String::from_utf8_lossy(&buffer[..size])) {
+                    Ok(data) if data == PROBE_MESSAGE => {
+                        trace!("answer probe from {}", addr);
+                        let _ = socket.send_to(auth::sign(&content).as_bytes(), addr);
+                    },
+                    Ok(data) => trace!("received probe from {} with wrong data {}", addr, data),
+                    Err(e) => warn!("reject probe from {}: {}", addr, e),
+                }
             }
         }
+    });
+}
+
+/// Probe a server over unicast
+/// 
+/// # Arguments
+/// * `server` - host name or ip address of the server, optional with the probe port `host:5888`
+/// * `timeout` - how long to wait for the answer
+/// * `dscp` - DSCP marking of the probe, see [`crate::qos`]
+/// 
+/// # Returns
+/// Option<(IpAddr, Announcement)> - the ip address of the server and its announcement
+pub fn probe_server(server: &str, timeout: Duration, dscp: Option<u8>) -> Option<(IpAddr, Announcement)> {
+    let addr = if server.contains(':') { server.to_string() } else { format!("{}:{}", server, PROBE_PORT) };
+    let addr = match addr.to_socket_addrs().ok().and_then(|mut addrs| addrs.find(|a| a.is_ipv4())) {
+        Some(addr) => addr,
+        None => {
+            warn!("could not resolve server {}", server);
+            return None;
+        }
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    qos::mark_socket(&socket, dscp);
+    socket.send_to(auth::sign(PROBE_MESSAGE).as_bytes(), addr).ok()?;
+
+    let start_instant = std::time::Instant::now();
+    let mut buffer = [0u8; 1500];
+    while let Some(remaining) = timeout.checked_sub(start_instant.elapsed()) {
+        let _ = socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))));
+        let (size, from) = socket.recv_from(&mut buffer).ok()?;
+        if from.ip() != addr.ip() {
+            continue;
+        }
+
+        match auth::verify(&String::from_utf8_lossy(&buffer[..size])).map_err(anyhow::Error::from).and_then(|data| data.parse::<Announcement>()) {
+            Ok(announcement) => {
+                debug!("server {} answered probe with {:?}", server, announcement);
+                return Some((from.ip(), announcement));
+            },
+            Err(e) => warn!("invalid probe answer from {}: {}", from, e),
+        }
     }
-    
     None
 }
 
+/// Probe the servers in order and return the first one which answers with an accepted announcement
+pub fn probe_servers(servers: &[String], timeout: Duration, dscp: Option<u8>, accept: impl Fn(&Announcement) -> bool) -> Option<(IpAddr, Announcement)> {
+    servers.iter()
+        .filter_map(|server| probe_server(server, timeout, dscp))
+        .find(|(address, announcement)| {
+            let accepted = accept(announcement);
+            if !accepted {
+                debug!("ignore server {} ({}), announcement not accepted", address, announcement.server_id);
+            }
+            accepted
+        })
+}
 
-pub fn confirm(server_ip: &str) {
-
+/// Load a list of servers from a file, one host per line, empty lines and lines starting with `#` are ignored
+pub fn load_server_list<P: AsRef<std::path::Path>>(path: P) -> Result<Vec<String>, anyhow::Error> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(content.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_string())
+        .collect())
+}
 
-    let content = format!("mirror|\n");
+/// Confirm the server that the client still receives the stream
+/// 
+/// # Arguments
+/// * `server_ip` - address of the server
+/// * `public_key` - key of the [`crate::encryption::KeyExchange`] of the client, the server sends the SRTP keys wrapped for it
+/// * `credential` - token of the client, for servers with a [`super::access::TokenStore`]. only a proof of the token is sent
+/// * `control_port` - port of the [`super::control::ControlListener`] of the client, if it listens
+/// * `dscp` - DSCP marking of the confirmation, see [`crate::qos`]
+pub fn confirm(server_ip: &str, public_key: Option<[u8; PUBLIC_KEY_LENGTH]>, credential: Option<&Credential>, control_port: Option<u16>, dscp: Option<u8>) {
+
+
+    let mut content = format!("{}|", CONFIRM_MAGIC);
+    if let Some(public_key) = public_key {
+        content.push_str(&format!("key={}|", hex::encode(public_key)));
+    }
+    if let Some(control_port) = control_port {
+        content.push_str(&format!("ctl={}|", control_port));
+    }
+    // the proof covers the fields before
+    if let Some(credential) = credential {
+        let proof = credential.prove(&content);
+        content.push_str(&proof);
+    }
+    content.push('\n');
     let addr = format!("{}:{}", server_ip, CONFIRMATION_PORT);
 
     thread::spawn(move || {
 
-        let content = &content;
+        let content = auth::sign(&content);
                     
         let try_socket = UdpSocket::bind(format!("0.0.0.0:0"));
         if let Ok(socket) = try_socket {
             socket.set_read_timeout(Some(std::time::Duration::from_millis(500))).unwrap();
-            let _ = socket.connect(addr);
+            qos::mark_socket(&socket, dscp);
+            let _ = socket.connect(&addr);
             let res = socket.send(content.as_bytes());
-            if res.is_err() {
-                // try to reconnect...
-                println!("think we got an error... {:?}", res);
+            if let Err(e) = res {
+                warn!("could not send confirmation to {}: {:?}", addr, e);
             }
 
         } else {
@@ -164,6 +393,51 @@ pub fn confirm(server_ip: &str) {
 }
 
 
+/// value of the field in a confirmation
+fn confirmation_field<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+    let mut fields = message.trim_end_matches(['\n', '\r']).split('|');
+    if fields.next() != Some(CONFIRM_MAGIC) {
+        return None;
+    }
+    fields.find_map(|field| field.strip_prefix(key).and_then(|field| field.strip_prefix('=')))
+}
+
+/// public key of the key exchange in a confirmation
+pub(crate) fn confirmation_key(message: &str) -> Option<[u8; PUBLIC_KEY_LENGTH]> {
+    confirmation_field(message, "key")
+        .and_then(|key| hex::decode(key).ok())
+        .and_then(|key| key.try_into().ok())
+}
+
+/// control port of the client in a confirmation
+pub(crate) fn confirmation_control_port(message: &str) -> Option<u16> {
+    confirmation_field(message, "ctl").and_then(|port| port.parse().ok())
+}
+
+/// proof of the token in a confirmation
+pub(crate) fn confirmation_proof(message: &str) -> Option<CredentialProof> {
+    confirmation_field(message, "proof").and_then(|_| CredentialProof::of(message))
+}
+
+/// Send a status report to the server over the confirmation path, marked with `dscp`
+pub fn send_report(server_ip: &str, report: &ClientReport, dscp: Option<u8>) {
+    let addr = format!("{}:{}", server_ip, CONFIRMATION_PORT);
+    match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => {
+            qos::mark_socket(&socket, dscp);
+            if let Err(e) = socket.send_to(auth::sign(&report.to_string()).as_bytes(), &addr) {
+                warn!("could not send report to {}: {:?}", addr, e);
+            }
+        },
+        Err(e) => warn!("error on create socket for report: {:?}", e),
+    }
+}
+
+
+/// Receive the confirmations and reports of the clients
+/// 
+/// # Returns
+/// the receiver of the client address with the whole message, and a sender to stop the thread
 pub fn thread_for_confirm() -> Result<(Receiver<(IpAddr, String)>, Sender<bool>), Box<dyn std::error::Error>> {
     let (send_client, receive_client) = unbounded::<(IpAddr, String)>();
     let (send_stop, recevie_stop) = unbounded::<bool>();
@@ -177,29 +451,38 @@ pub fn thread_for_confirm() -> Result<(Receiver<(IpAddr, String)>, Sender<bool>)
             let try_socket = UdpSocket::bind(("0.0.0.0", CONFIRMATION_PORT));
             if let Ok(socket) = try_socket {
                 info!("create socket for confirmation on port {}", CONFIRMATION_PORT);
+                // the timeout only wakes up the loop to check for stop, datagrams are handled without delay
+                let _ = socket.set_read_timeout(Some(Duration::from_millis(300)));
                 while keep_runnin {
                     // if we receive stop, stop!
                     if let Ok(stop) = recevie_stop.try_recv() {
                         keep_runnin = stop;
                     }
-                    let mut buffer = [0u8; 256];
+                    let mut buffer = [0u8; 1500];
                     let res = socket.recv_from(&mut buffer);
                     match res {
                         Ok((size, addr)) => {
-                            let data = std::str::from_utf8(&buffer[..size]).unwrap();
+                            let data = match auth::verify(&String::from_utf8_lossy(&buffer[..size])) {
+                                Ok(data) => data,
+                                Err(e) => {
+                                    warn!("reject confirmation from {}: {}", addr, e);
+                                    continue
+                                }
+                            };
                             let d: Vec<&str> = data.split("|").collect();
-                            if d.len() < 1 {
+                            if d.len() < 2 {
                                 trace!("received confirmation from {} with wrong data {}", addr, data);
-                                break
+                                continue
                             }
                             trace!("received confirmation from {} with {}", addr, data);
-                            send_client.try_send((addr.clone().ip(), d[1].to_string()));
+                            let _ = send_client.try_send((addr.ip(), data));
                         },
+                        Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => (),
                         Err(e) => {
                             trace!("error on recv from confirmation: {:?}", e);
+                            sleep_ms!(300);
                         }
                     }
-                    sleep_ms!(300);
                 }
 
             } else {
@@ -219,4 +502,63 @@ pub fn thread_for_confirm() -> Result<(Receiver<(IpAddr, String)>, Sender<bool>)
     });
 
     Ok((receive_client, send_stop))
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(allow: &[&str], deny: &[&str]) -> InterfaceFilter {
+        InterfaceFilter {
+            allow: allow.iter().map(|entry| entry.to_string()).collect(),
+            deny: deny.iter().map(|entry| entry.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn interface_filter() {
+        assert!(InterfaceFilter::default().matches("eth0"));
+
+        let allow = filter(&["eth*", "wlan0"], &[]);
+        assert!(allow.matches("eth0"));
+        assert!(allow.matches("eth1"));
+        assert!(allow.matches("wlan0"));
+        assert!(!allow.matches("wlan1"));
+        assert!(!allow.matches("docker0"));
+
+        // the deny list wins
+        let deny = filter(&["eth*"], &["eth1", "docker*"]);
+        assert!(deny.matches("eth0"));
+        assert!(!deny.matches("eth1"));
+        assert!(!filter(&[], &["docker*"]).matches("docker0"));
+        assert!(filter(&[], &["docker*"]).matches("eth0"));
+        assert!(!filter(&["eth*"], &["eth*"]).matches("eth0"));
+    }
+
+    #[test]
+    fn broadcast_address() {
+        let broadcast = |ip: [u8; 4], netmask: [u8; 4]| directed_broadcast(Ipv4Addr::from(ip), Ipv4Addr::from(netmask));
+        assert_eq!(broadcast([10, 0, 0, 5], [255, 255, 254, 0]), Ipv4Addr::new(10, 0, 1, 255));
+        assert_eq!(broadcast([10, 0, 1, 5], [255, 255, 254, 0]), Ipv4Addr::new(10, 0, 1, 255));
+        assert_eq!(broadcast([172, 16, 3, 4], [255, 255, 0, 0]), Ipv4Addr::new(172, 16, 255, 255));
+        assert_eq!(broadcast([192, 168, 1, 20], [255, 255, 255, 0]), Ipv4Addr::new(192, 168, 1, 255));
+        assert_eq!(broadcast([192, 168, 1, 20], [255, 255, 255, 255]), Ipv4Addr::new(192, 168, 1, 20));
+    }
+
+    #[test]
+    fn server_list() {
+        let path = std::env::temp_dir().join(format!("micast-servers-{}", auth::nonce()));
+        std::fs::write(&path, "# studio
+server-b.local
+
+  10.0.0.7  
+	
+  # spare
+server-a.local:5888
+").unwrap();
+        let servers = load_server_list(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(servers.unwrap(), vec!["server-b.local", "10.0.0.7", "server-a.local:5888"]);
+
+        assert!(load_server_list(&path).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+/// DNS-SD service advertisement over mDNS
+///
+/// works across VLANs with an mDNS reflector, where the broadcast on port 5889 does not.
+/// the TXT records carry the same fields as the discovery [`Announcement`].
+/// with a shared secret the `auth` record carries the signature of the other records,
+/// it is renewed every [`SIGNATURE_REFRESH`] because the records are repeated unchanged
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, RecvTimeoutError, Sender};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use log::{debug, info, trace, warn};
+
+use super::auth;
+use super::discovery::{escape, unescape, Announcement};
+
+/// service type of the broadcaster
+pub const MDNS_SERVICE_TYPE: &str = "_micast._udp.local.";
+/// TXT record with the signature of the other records
+const AUTH_PROPERTY: &str = "auth";
+/// interval to renew the signature, well within the [`auth::REPLAY_WINDOW`]
+const SIGNATURE_REFRESH: Duration = Duration::from_secs(10);
+
+/// TXT records of the announcement
+fn txt_properties(announcement: &Announcement) -> HashMap<String, String> {
+    let mut properties = HashMap::new();
+    properties.insert("v".to_string(), announcement.version.to_string());
+    properties.insert("id".to_string(), announcement.server_id.clone());
+    properties.insert("name".to_string(), announcement.name.clone());
+    properties.insert("rtp".to_string(), announcement.rtp_port.to_string());
+    if let Some(clock_port) = announcement.clock_port {
+        properties.insert("clock".to_string(), clock_port.to_string());
+    }
+    properties.insert("codec".to_string(), announcement.codec.clone());
+    if let Some(multicast_group) = &announcement.multicast_group {
+        properties.insert("mcast".to_string(), multicast_group.clone());
+    }
+    if !announcement.channels.is_empty() {
+        // channel names may contain the separator
+        let channels: Vec<String> = announcement.channels.iter().map(|channel| escape(channel)).collect();
+        properties.insert("channels".to_string(), channels.join(","));
+    }
+    if let Some(zone) = &announcement.zone {
+        properties.insert("zone".to_string(), zone.clone());
+    }
+    properties.insert("prio".to_string(), announcement.priority.to_string());
+    properties.insert("srtp".to_string(), (announcement.encrypted as u8).to_string());
+    if announcement.encrypted {
+        properties.insert("suite".to_string(), announcement.cipher_suite.clone());
+    }
+    properties
+}
+
+/// the TXT records without the signature, sorted to a message for the signature
+fn signed_message<'a>(properties: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut properties: Vec<_> = properties.filter(|(key, _)| *key != AUTH_PROPERTY).collect();
+    properties.sort();
+    properties.iter().map(|(key, value)| format!("{}={}|", key, escape(value))).collect()
+}
+
+/// TXT records of the announcement with their signature, unsigned without a shared secret
+fn signed_txt_properties(announcement: &Announcement) -> HashMap<String, String> {
+    let mut properties = txt_properties(announcement);
+    let message = signed_message(properties.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+    let signed = auth::sign(&message);
+    match signed.strip_prefix(&message).map(|signature| signature.trim_end()) {
+        Some(signature) if !signature.is_empty() => {
+            properties.insert(AUTH_PROPERTY.to_string(), signature.to_string());
+        },
+        _ => {}
+    }
+    properties
+}
+
+/// Announcement from the TXT records of a resolved service
+fn announcement_of(info: &ServiceInfo) -> Result<Announcement, anyhow::Error> {
+    let get = |key: &str| info.get_property_val_str(key);
+    auth::verify_record(&signed_record(info))?;
+
+    let rtp_port = match get("rtp") {
+        Some(port) => port.parse()?,
+        None => info.get_port() as u32,
+    };
+
+    let mut announcement = Announcement::new(get("id").unwrap_or(""), get("name").unwrap_or(""), rtp_port);
+    if let Some(version) = get("v") {
+        announcement.version = version.parse()?;
+    }
+    announcement.clock_port = get("clock").map(|port| port.parse()).transpose()?;
+    if let Some(codec) = get("codec") {
+        announcement.codec = codec.to_string();
+    }
+    announcement.multicast_group = get("mcast").map(|group| group.to_string());
+    announcement.channels = get("channels")
+        .map(|channels| channels.split(',').filter(|c| !c.is_empty()).map(unescape).collect::<Result<_, _>>())
+        .transpose()?
+        .unwrap_or_default();
+    announcement.zone = get("zone").map(|zone| zone.to_string());
+    announcement.priority = get("prio").map(|priority| priority.parse()).transpose()?.unwrap_or(0);
+    announcement.encrypted = get("srtp") == Some("1");
+    if let Some(suite) = get("suite") {
+        announcement.cipher_suite = suite.to_string();
+    }
+
+    Ok(announcement)
+}
+
+/// the TXT records with their signature as the signed message of [`auth`]
+fn signed_record(info: &ServiceInfo) -> String {
+    let message = signed_message(info.get_properties().iter().map(|property| (property.key(), property.val_str())));
+    format!("{}{}", message, info.get_property_val_str(AUTH_PROPERTY).unwrap_or(""))
+}
+
+/// instance names are labels, dots would split them
+fn instance_name(announcement: &Announcement) -> String {
+    let name = if announcement.name.is_empty() { &announcement.server_id } else { &announcement.name };
+    name.replace('.', "-")
+}
+
+fn service_info(announcement: &Announcement) -> Result<ServiceInfo, anyhow::Error> {
+    let host_name = format!("{}.local.", announcement.server_id.replace('.', "-"));
+    let info = ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &instance_name(announcement),
+        &host_name,
+        (),
+        announcement.rtp_port as u16,
+        signed_txt_properties(announcement),
+    )?.enable_addr_auto();
+    Ok(info)
+}
+
+/// Advertises the server as `_micast._udp` service as long as it lives
+pub struct MdnsAdvertiser {
+    daemon: ServiceDaemon,
+    fullname: String,
+    stop_sender: Sender<bool>,
+}
+
+impl std::fmt::Debug for MdnsAdvertiser {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MdnsAdvertiser").field("fullname", &self.fullname).finish()
+    }
+}
+
+impl MdnsAdvertiser {
+    pub fn new(announcement: &Announcement) -> Result<Self, anyhow::Error> {
+        let daemon = ServiceDaemon::new()?;
+
+        let info = service_info(announcement)?;
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info)?;
+        info!("advertise {} via mdns", fullname);
+
+        let (stop_sender, receive_stop) = unbounded::<bool>();
+        if auth::is_enabled() {
+            let cloned_daemon = daemon.clone();
+            let announcement = announcement.clone();
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = receive_stop.recv_timeout(SIGNATURE_REFRESH) {
+                    // registering again replaces the TXT records and announces them
+                    trace!("renew the signature of the mdns txt records");
+                    if let Err(e) = service_info(&announcement).and_then(|info| Ok(cloned_daemon.register(info)?)) {
+                        warn!("could not renew the mdns txt records: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        Ok(MdnsAdvertiser { daemon, fullname, stop_sender })
+    }
+}
+
+impl Drop for MdnsAdvertiser {
+    fn drop(&mut self) {
+        let _ = self.stop_sender.send(true);
+        if let Err(e) = self.daemon.unregister(&self.fullname) {
+            warn!("could not unregister mdns service {}: {:?}", self.fullname, e);
+        }
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Browse for servers via mDNS
+///
+/// returns after the timeout or if `first_only` is set after the first resolved and accepted server
+fn browse(timeout: Duration, first_only: bool, accept: impl Fn(&Announcement) -> bool) -> Vec<(IpAddr, Announcement)> {
+    let mut servers = Vec::new();
+
+    let daemon = match ServiceDaemon::new() {
+        Ok(daemon) => daemon,
+        Err(e) => {
+            warn!("could not start mdns daemon: {:?}", e);
+            return servers;
+        }
+    };
+    let receiver = match daemon.browse(MDNS_SERVICE_TYPE) {
+        Ok(receiver) => receiver,
+        Err(e) => {
+            warn!("could not browse for {}: {:?}", MDNS_SERVICE_TYPE, e);
+            let _ = daemon.shutdown();
+            return servers;
+        }
+    };
+
+    let start_instant = Instant::now();
+    while let Some(remaining) = timeout.checked_sub(start_instant.elapsed()) {
+        let info = match receiver.recv_timeout(remaining) {
+            Ok(ServiceEvent::ServiceResolved(info)) => info,
+            Ok(event) => {
+                trace!("mdns event {:?}", event);
+                continue;
+            },
+            Err(_) => break,
+        };
+
+        let address = match info.get_addresses_v4().into_iter().next() {
+            Some(address) => IpAddr::V4(*address),
+            None => continue,
+        };
+
+        match announcement_of(&info) {
+            Ok(announcement) if !accept(&announcement) => {
+                debug!("ignore {} at {}, announcement not accepted", info.get_fullname(), address);
+            },
+            Ok(announcement) => {
+                debug!("found {} at {} via mdns", info.get_fullname(), address);
+                servers.push((address, announcement));
+                if first_only {
+                    break;
+                }
+            },
+            Err(e) => warn!("invalid txt records of {}: {:?}", info.get_fullname(), e),
+        }
+    }
+
+    let _ = daemon.stop_browse(MDNS_SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    servers
+}
+
+/// Wait a specific Duration for the first server found via mDNS whose announcement is accepted
+///
+/// # Returns
+/// Option<(IpAddr, Announcement)> - the ip address of the server and its announcement
+pub fn wait_for_mdns(timeout: Duration, accept: impl Fn(&Announcement) -> bool) -> Option<(IpAddr, Announcement)> {
+    browse(timeout, true, accept).into_iter().next()
+}
+
+/// All servers found via mDNS within the timeout
+pub fn discover_mdns(timeout: Duration) -> Vec<(IpAddr, Announcement)> {
+    browse(timeout, false, |_| true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"deployment secret";
+
+    fn announcement() -> Announcement {
+        let mut announcement = Announcement::new("server-1", "Living Room", 5004);
+        announcement.multicast_group = Some("239.69.1.1".to_string());
+        announcement.channels = vec!["left,front".to_string(), "right|rear".to_string(), "100%".to_string()];
+        announcement.zone = Some("first floor".to_string());
+        announcement.priority = 3;
+        announcement.encrypted = true;
+        announcement
+    }
+
+    /// the TXT records signed like [`signed_txt_properties`] does with the secret
+    fn signed(mut properties: HashMap<String, String>) -> HashMap<String, String> {
+        let message = signed_message(properties.iter().map(|(key, value)| (key.as_str(), value.as_str())));
+        let signature = auth::sign_with(SECRET, &message).strip_prefix(&message).unwrap().trim_end().to_string();
+        properties.insert(AUTH_PROPERTY.to_string(), signature);
+        properties
+    }
+
+    fn info(properties: HashMap<String, String>) -> ServiceInfo {
+        ServiceInfo::new(MDNS_SERVICE_TYPE, "Living Room", "server-1.local.", (), 5004, properties).unwrap()
+    }
+
+    #[test]
+    fn txt_round_trip() {
+        let announcement = announcement();
+        let info = service_info(&announcement).unwrap();
+        assert!(info.get_property_val_str(AUTH_PROPERTY).is_none());
+        assert_eq!(announcement_of(&info).unwrap(), announcement);
+
+        let mut announcement = Announcement::new("server.1", "", 5006);
+        announcement.clock_port = None;
+        assert_eq!(instance_name(&announcement), "server-1");
+        assert_eq!(announcement_of(&service_info(&announcement).unwrap()).unwrap(), announcement);
+    }
+
+    #[test]
+    fn signed_message_is_sorted() {
+        let message = signed_message([("rtp", "5004"), (AUTH_PROPERTY, "sig=00|"), ("id", "a|b")].into_iter());
+        assert_eq!(message, "id=a%7Cb|rtp=5004|");
+    }
+
+    #[test]
+    fn signed_txt_records() {
+        let properties = signed(txt_properties(&announcement()));
+        let record = signed_record(&info(properties.clone()));
+        assert!(auth::verify_with(SECRET, &record, false).is_ok());
+        // the records are repeated unchanged
+        assert!(auth::verify_with(SECRET, &record, false).is_ok());
+
+        let mut changed = properties.clone();
+        changed.insert("prio".to_string(), "9".to_string());
+        assert_eq!(auth::verify_with(SECRET, &signed_record(&info(changed)), false), Err(auth::AuthError::Invalid));
+
+        let mut added = properties.clone();
+        added.insert("mcast".to_string(), "239.69.1.2".to_string());
+        assert_eq!(auth::verify_with(SECRET, &signed_record(&info(added)), false), Err(auth::AuthError::Invalid));
+
+        let mut unsigned = properties;
+        unsigned.remove(AUTH_PROPERTY);
+        assert_eq!(auth::verify_with(SECRET, &signed_record(&info(unsigned)), false), Err(auth::AuthError::Unsigned));
+    }
+}
@@ -1,8 +1,21 @@
 pub mod dedector_server;
+pub mod sap;
+pub mod discovery;
+pub mod mdns;
+pub mod auth;
+pub mod control;
+pub mod report;
+pub mod access;
 mod informip;
 pub use informip::wait_for_broadcast;
+pub use informip::collect_broadcasts;
+pub use informip::InterfaceFilter;
+pub use informip::{probe_server, probe_servers, load_server_list};
 pub use informip::confirm;
 pub use informip::thread_for_confirm;
+pub use informip::send_report;
+pub(crate) use informip::{confirmation_key, confirmation_proof, confirmation_control_port};
 
 pub const RECONFIRMATIONTIME_IN_MS: u64 = 1200;
-pub const TIMEOUT_CONFIRM_IN_MS: u64 = 5000;
\ No newline at end of file
+pub const TIMEOUT_CONFIRM_IN_MS: u64 = 5000;
+pub const REPORT_INTERVAL_IN_MS: u64 = 5000;
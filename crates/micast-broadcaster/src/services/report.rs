@@ -0,0 +1,146 @@
+/// Status report of a client, send periodically over the confirmation path
+///
+/// `report|ver=<crate version>|state=<state>|jb=<percent>|jitter=<us>|lost=<packets>|offset=<ns>|rtt=<us>|out=<element>|dev=<device>|vol=<volume>|mute=<0/1>|err=<a>,<b>|`
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use super::discovery::{escape, unescape};
+
+pub(crate) const REPORT_MAGIC: &str = "report";
+
+/// Status of a `PlaybackClient`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ClientReport {
+    /// crate version of the client
+    pub version: String,
+    /// state of the pipeline, e.g. `Playing`
+    pub state: String,
+    /// fill level of the jitterbuffer in percent
+    pub jitterbuffer_percent: Option<i32>,
+    /// average jitter of the received packets in µs
+    pub jitter_us: Option<u64>,
+    /// packets lost in the jitterbuffer
+    pub packets_lost: u64,
+    /// offset of the synced clock to the local clock in ns
+    pub clock_offset_ns: Option<i64>,
+    /// round trip time to the clock provider in µs
+    pub rtt_us: Option<u64>,
+    pub output_element: String,
+    pub output_device: String,
+    pub volume: f64,
+    pub muted: bool,
+    /// recent errors of the pipeline, the newest last
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for ClientReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}|ver={}|state={}|", REPORT_MAGIC, escape(&self.version), escape(&self.state))?;
+        if let Some(percent) = self.jitterbuffer_percent {
+            write!(f, "jb={}|", percent)?;
+        }
+        if let Some(jitter) = self.jitter_us {
+            write!(f, "jitter={}|", jitter)?;
+        }
+        write!(f, "lost={}|", self.packets_lost)?;
+        if let Some(offset) = self.clock_offset_ns {
+            write!(f, "offset={}|", offset)?;
+        }
+        if let Some(rtt) = self.rtt_us {
+            write!(f, "rtt={}|", rtt)?;
+        }
+        write!(f, "out={}|dev={}|vol={}|mute={}|", escape(&self.output_element), escape(&self.output_device), self.volume, self.muted as u8)?;
+        if !self.errors.is_empty() {
+            let errors: Vec<String> = self.errors.iter().map(|e| escape(e)).collect();
+            write!(f, "err={}|", errors.join(","))?;
+        }
+        writeln!(f)
+    }
+}
+
+impl FromStr for ClientReport {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.trim_end_matches(['\n', '\r']).split('|');
+        if fields.next() != Some(REPORT_MAGIC) {
+            return Err(anyhow!("not a client report"));
+        }
+
+        let mut report = ClientReport::default();
+        for field in fields.filter(|field| !field.is_empty()) {
+            let (key, value) = field.split_once('=').ok_or_else(|| anyhow!("invalid field {}", field))?;
+            match key {
+                "ver" => report.version = unescape(value)?,
+                "state" => report.state = unescape(value)?,
+                "jb" => report.jitterbuffer_percent = Some(value.parse()?),
+                "jitter" => report.jitter_us = Some(value.parse()?),
+                "lost" => report.packets_lost = value.parse()?,
+                "offset" => report.clock_offset_ns = Some(value.parse()?),
+                "rtt" => report.rtt_us = Some(value.parse()?),
+                "out" => report.output_element = unescape(value)?,
+                "dev" => report.output_device = unescape(value)?,
+                "vol" => report.volume = value.parse()?,
+                "mute" => report.muted = value == "1",
+                "err" => {
+                    report.errors = value.split(',')
+                        .filter(|e| !e.is_empty())
+                        .map(unescape)
+                        .collect::<Result<_, _>>()?;
+                },
+                // fields of newer clients
+                _ => {},
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_round_trip() {
+        let report = ClientReport {
+            version: "0.3.0".to_string(),
+            state: "Playing".to_string(),
+            jitterbuffer_percent: Some(80),
+            jitter_us: Some(1250),
+            packets_lost: 3,
+            clock_offset_ns: Some(-42000),
+            rtt_us: Some(900),
+            output_element: "alsasink".to_string(),
+            output_device: "hw:CARD=a|b,0".to_string(),
+            volume: 0.75,
+            muted: true,
+            errors: vec!["no device, retry".to_string(), "timeout=5s".to_string()],
+        };
+        assert_eq!(report.to_string().parse::<ClientReport>().unwrap(), report);
+
+        // unknown values are left out
+        let report = ClientReport { state: "Paused".to_string(), ..ClientReport::default() };
+        let message = report.to_string();
+        assert!(!message.contains("jb=") && !message.contains("err="));
+        assert_eq!(message.parse::<ClientReport>().unwrap(), report);
+    }
+
+    #[test]
+    fn fields_of_newer_clients_are_ignored() {
+        let report = "report|ver=1.0|state=Playing|lost=0|new=field|vol=1|\n".parse::<ClientReport>().unwrap();
+        assert_eq!(report.version, "1.0");
+        assert_eq!(report.volume, 1.0);
+    }
+
+    #[test]
+    fn invalid_reports() {
+        assert!("mirror|key=00|".parse::<ClientReport>().is_err());
+        assert!("report|lost|".parse::<ClientReport>().is_err());
+        assert!("report|lost=many|".parse::<ClientReport>().is_err());
+        assert!("report|vol=loud|".parse::<ClientReport>().is_err());
+        assert!("report|err=%1|".parse::<ClientReport>().is_err());
+    }
+}
@@ -0,0 +1,340 @@
+// SAP (RFC 2974) session announcements 
+// 
+// sends the SDP of the stream periodically to the SAP multicast group,
+// so AES67 receivers and other players can find the stream.
+// the SapListener collects the announced sessions of all senders
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_channel::{unbounded, Sender, RecvTimeoutError};
+use parking_lot::Mutex;
+use log::{debug, trace, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::sdp::SessionDescription;
+use crate::sleep_ms;
+use crate::qos;
+
+pub const SAP_PORT: u16 = 9875;
+/// SAP group of the administratively scoped range 239.255.0.0/16, used by AES67 devices
+pub const SAP_ADDRESS: Ipv4Addr = Ipv4Addr::new(239, 255, 255, 255);
+/// SAP group of the global scope
+pub const SAP_GLOBAL_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 2, 127, 254);
+/// interval between two announcements
+pub const SAP_INTERVAL: Duration = Duration::from_secs(30);
+
+const SAP_MIME_TYPE: &str = "application/sdp";
+/// sessions are removed if they are not announced again in this time 
+/// RFC 2974 recommends 10 times the announcement interval, but at least one hour.
+/// we use a shorter time, so the list of sessions follows the network 
+const SAP_SESSION_TIMEOUT: Duration = Duration::from_secs(3 * 30);
+
+/// Options for SAP announcements
+#[derive(Debug, Clone, PartialEq)]
+pub struct SapOptions {
+    /// multicast group where the announcements are send to
+    pub address: Ipv4Addr,
+    /// interval between two announcements
+    pub interval: Duration,
+}
+
+impl Default for SapOptions {
+    fn default() -> Self {
+        SapOptions {
+            address: SAP_GLOBAL_ADDRESS,
+            interval: SAP_INTERVAL,
+        }
+    }
+}
+
+/// a session announced via SAP
+#[derive(Debug, Clone)]
+pub struct SapSession {
+    /// address of the announcing server
+    pub source: IpAddr,
+    /// announced session
+    pub description: SessionDescription,
+    /// time of the last announcement
+    pub last_seen: Instant,
+    msg_id_hash: u16,
+}
+
+/// creates a SAP packet (version 1, IPv4, no authentication) with the SDP as payload
+pub(crate) fn sap_packet(sdp: &str, origin: Ipv4Addr, deletion: bool) -> Vec<u8> {
+    // the message id hash has to change if the sdp changes
+    let mut hasher = DefaultHasher::new();
+    sdp.hash(&mut hasher);
+    let msg_id_hash = hasher.finish() as u16;
+
+    let mut packet = Vec::with_capacity(8 + SAP_MIME_TYPE.len() + 1 + sdp.len());
+    // V=1, A=0 (IPv4), R=0, T=announce or deletion, E=0, C=0
+    packet.push(if deletion { 0x24 } else { 0x20 });
+    // no authentication data
+    packet.push(0);
+    packet.extend_from_slice(&msg_id_hash.to_be_bytes());
+    packet.extend_from_slice(&origin.octets());
+    packet.extend_from_slice(SAP_MIME_TYPE.as_bytes());
+    packet.push(0);
+    packet.extend_from_slice(sdp.as_bytes());
+
+    packet
+}
+
+/// parse a SAP packet, returns the message id hash, if it is a deletion and the SDP
+pub(crate) fn parse_sap_packet(packet: &[u8]) -> Option<(u16, bool, String)> {
+    if packet.len() < 4 {
+        return None;
+    }
+
+    let flags = packet[0];
+    // only version 1, no encryption and no compression is supported
+    if flags >> 5 != 1 || flags & 0x02 != 0 || flags & 0x01 != 0 {
+        return None;
+    }
+    let deletion = flags & 0x04 != 0;
+    let address_len = if flags & 0x10 != 0 { 16 } else { 4 };
+    let auth_len = packet[1] as usize * 4;
+    let msg_id_hash = u16::from_be_bytes([packet[2], packet[3]]);
+
+    let mut payload = packet.get(4 + address_len + auth_len..)?;
+
+    // optional payload type, the sdp itself starts with v=0
+    if !payload.starts_with(b"v=0") {
+        let end = payload.iter().position(|b| *b == 0)?;
+        if &payload[..end] != SAP_MIME_TYPE.as_bytes() {
+            return None;
+        }
+        payload = &payload[end + 1..];
+    }
+
+    let sdp = std::str::from_utf8(payload).ok()?;
+    Some((msg_id_hash, deletion, sdp.to_string()))
+}
+
+fn origin_address() -> Ipv4Addr {
+    match local_ip_address::local_ip() {
+        Ok(IpAddr::V4(ip)) => ip,
+        _ => Ipv4Addr::LOCALHOST,
+    }
+}
+
+/// Announces a SDP periodically via SAP until it gets dropped
+#[derive(Debug)]
+pub struct SapAnnouncer {
+    sdp: Arc<Mutex<String>>,
+    stop_sender: Sender<bool>,
+}
+
+impl SapAnnouncer {
+    /// starts the announcements of `sdp` every `interval` to the SAP group `address`, marked with `dscp`
+    pub fn new(sdp: String, address: Ipv4Addr, interval: Duration, dscp: Option<u8>) -> Self {
+        let sdp = Arc::new(Mutex::new(sdp));
+        let (stop_sender, receive_stop) = unbounded::<bool>();
+
+        let cloned_sdp = sdp.clone();
+        thread::spawn(move || {
+            let socket = match UdpSocket::bind("0.0.0.0:0") {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!("error on create socket for sap announcements: {:?}", e);
+                    return
+                }
+            };
+            let _ = socket.set_multicast_ttl_v4(16);
+            qos::mark_socket(&socket, dscp);
+            let origin = origin_address();
+
+            loop {
+                let sdp = cloned_sdp.lock().clone();
+                trace!("send sap announcement to {}:{}", address, SAP_PORT);
+                if let Err(e) = socket.send_to(&sap_packet(&sdp, origin, false), (address, SAP_PORT)) {
+                    warn!("error on send sap announcement: {:?}", e);
+                }
+
+                match receive_stop.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => {
+                        debug!("stop sap announcements");
+                        let _ = socket.send_to(&sap_packet(&sdp, origin, true), (address, SAP_PORT));
+                        break
+                    }
+                }
+            }
+        });
+
+        SapAnnouncer { sdp, stop_sender }
+    }
+
+    /// replace the announced SDP, will be send with the next announcement
+    pub fn set_sdp(&self, sdp: String) {
+        *self.sdp.lock() = sdp;
+    }
+}
+
+impl Drop for SapAnnouncer {
+    fn drop(&mut self) {
+        let _ = self.stop_sender.send(true);
+    }
+}
+
+
+/// Collects the sessions announced via SAP until it gets dropped
+#[derive(Debug)]
+pub struct SapListener {
+    sessions: Arc<Mutex<HashMap<(IpAddr, u64), SapSession>>>,
+    stop_sender: Sender<bool>,
+}
+
+impl SapListener {
+    /// listen for announcements on the global and the administratively scoped SAP group
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        // other SAP listeners may run on the same host
+        socket.set_reuse_address(true)?;
+        socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, SAP_PORT)).into())?;
+        let socket: UdpSocket = socket.into();
+        socket.join_multicast_v4(&SAP_GLOBAL_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+        socket.join_multicast_v4(&SAP_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_read_timeout(Some(Duration::from_millis(500)))?;
+
+        let sessions = Arc::new(Mutex::new(HashMap::<(IpAddr, u64), SapSession>::new()));
+        let (stop_sender, receive_stop) = unbounded::<bool>();
+
+        let cloned_sessions = sessions.clone();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while receive_stop.try_recv().is_err() {
+                let (size, addr) = match socket.recv_from(&mut buffer) {
+                    Ok(res) => res,
+                    Err(e) => {
+                        trace!("error on recv sap announcement: {:?}", e);
+                        continue
+                    }
+                };
+
+                let (msg_id_hash, deletion, sdp) = match parse_sap_packet(&buffer[..size]) {
+                    Some(packet) => packet,
+                    None => {
+                        warn!("received invalid sap packet from {}", addr);
+                        continue
+                    }
+                };
+
+                let description = match sdp.parse::<SessionDescription>() {
+                    Ok(description) => description,
+                    Err(e) => {
+                        warn!("received sap packet from {} with invalid sdp: {:?}", addr, e);
+                        continue
+                    }
+                };
+
+                let key = (addr.ip(), description.session_id);
+                let mut sessions = cloned_sessions.lock();
+                if deletion {
+                    debug!("sap session {} from {} deleted", description.session_name, addr);
+                    sessions.remove(&key);
+                } else {
+                    match sessions.get(&key) {
+                        Some(session) if session.msg_id_hash != msg_id_hash => {
+                            debug!("sap session {} from {} changed", description.session_name, addr);
+                        },
+                        _ => trace!("sap session {} from {} announced", description.session_name, addr),
+                    }
+                    sessions.insert(key, SapSession {
+                        source: addr.ip(),
+                        description,
+                        last_seen: Instant::now(),
+                        msg_id_hash,
+                    });
+                }
+
+                sessions.retain(|_, session| session.last_seen.elapsed() < SAP_SESSION_TIMEOUT);
+            }
+
+            debug!("stop sap listener");
+        });
+
+        Ok(SapListener { sessions, stop_sender })
+    }
+
+    /// all currently announced sessions
+    pub fn sessions(&self) -> Vec<SapSession> {
+        let mut sessions = self.sessions.lock();
+        sessions.retain(|_, session| session.last_seen.elapsed() < SAP_SESSION_TIMEOUT);
+        sessions.values().cloned().collect()
+    }
+}
+
+impl Drop for SapListener {
+    fn drop(&mut self) {
+        let _ = self.stop_sender.send(true);
+    }
+}
+
+/// listen `timeout` for SAP announcements and return all announced sessions
+pub fn discover_sessions(timeout: Duration) -> Result<Vec<SapSession>, anyhow::Error> {
+    let listener = SapListener::new()?;
+    sleep_ms!(timeout.as_millis() as u64);
+    Ok(listener.sessions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SDP: &str = "v=0\r\no=- 1 1 IN IP4 192.168.1.2\r\ns=micast-dj\r\nc=IN IP4 239.69.1.1/32\r\nt=0 0\r\nm=audio 5004 RTP/AVP 96\r\n";
+
+    #[test]
+    fn packet_round_trip() {
+        let packet = sap_packet(SDP, Ipv4Addr::new(192, 168, 1, 2), false);
+        assert_eq!(&packet[4..8], &[192, 168, 1, 2]);
+
+        let (msg_id_hash, deletion, sdp) = parse_sap_packet(&packet).unwrap();
+        assert_eq!(msg_id_hash, u16::from_be_bytes([packet[2], packet[3]]));
+        assert!(!deletion);
+        assert_eq!(sdp, SDP);
+
+        let (deletion_hash, deletion, _) = parse_sap_packet(&sap_packet(SDP, Ipv4Addr::LOCALHOST, true)).unwrap();
+        assert_eq!(deletion_hash, msg_id_hash);
+        assert!(deletion);
+    }
+
+    #[test]
+    fn message_id_hash_changes_with_the_sdp() {
+        let packet = sap_packet(SDP, Ipv4Addr::LOCALHOST, false);
+        let changed = sap_packet(&SDP.replace("5004", "5006"), Ipv4Addr::LOCALHOST, false);
+        assert_ne!(packet[2..4], changed[2..4]);
+    }
+
+    #[test]
+    fn packet_without_payload_type() {
+        // the payload type is optional, authentication data is skipped
+        let mut packet = vec![0x20, 1, 0x12, 0x34, 10, 0, 0, 1, 0, 0, 0, 0];
+        packet.extend_from_slice(SDP.as_bytes());
+        assert_eq!(parse_sap_packet(&packet), Some((0x1234, false, SDP.to_string())));
+    }
+
+    #[test]
+    fn unsupported_packets() {
+        let packet = sap_packet(SDP, Ipv4Addr::LOCALHOST, false);
+
+        // version 0, encrypted and compressed packets
+        for flags in [0x00, 0x22, 0x21] {
+            let mut unsupported = packet.clone();
+            unsupported[0] = flags;
+            assert_eq!(parse_sap_packet(&unsupported), None);
+        }
+
+        let mut other_type = sap_packet(SDP, Ipv4Addr::LOCALHOST, false);
+        other_type[8] = b'x';
+        assert_eq!(parse_sap_packet(&other_type), None);
+
+        assert_eq!(parse_sap_packet(&packet[..3]), None);
+        assert_eq!(parse_sap_packet(&packet[..6]), None);
+    }
+}